@@ -1,9 +1,17 @@
-import React from 'react';
+import React, { useEffect } from 'react';
+import axios from 'axios';
 import Container from 'react-bootstrap/Container';
 import Auth from './Auth';
 import './App.css';
 
 function App() {
+  // An expired access token lands here, a live session goes right back
+  useEffect(() => {
+    axios.post(`/refresh`)
+      .then(() => window.location.replace("/"))
+      .catch(() => {});
+  }, []);
+
   return (
   <Container className="p-3">
       <Auth/>
@@ -1,9 +1,19 @@
 use super::jwt::MasterTokenSecret;
+use super::password::{hash_password, verify_nothing, verify_password, Verified};
+use super::session::{self, ACCESS_TOKEN_TTL, REFRESH_TOKEN_TTL};
 use super::{jwt, UserCtx};
-use crate::auth::md5::hash_password;
+use crate::limit::{RateLimiter, TooManyRequests};
 use crate::model::db::Db;
+use crate::model::lockouts::LockoutMac;
 use crate::model::users::UserMac;
+use crate::model::IdType;
+use crate::rejection::error_reply;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use warp::http::header::{HeaderValue, SET_COOKIE};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::Reply;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserAuthReply {
@@ -23,18 +33,103 @@ pub struct UserSignup {
     pub password: String,
 }
 
-fn token_reply(token: &str) -> Result<impl warp::Reply, warp::Rejection> {
+impl UserSignup {
+    /// Why the signup is refused, if it is.
+    fn validate(&self) -> Result<(), &'static str> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > 32 || name.chars().any(char::is_control) {
+            return Err("name must be 1 to 32 characters");
+        }
+        let valid_email = match self.email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !domain.contains('@')
+            }
+            None => false,
+        };
+        if !valid_email || self.email.len() > 254 || self.email.contains(char::is_whitespace) {
+            return Err("invalid email");
+        }
+        let length = self.password.chars().count();
+        if !(8..=128).contains(&length) {
+            return Err("password must be 8 to 128 characters");
+        }
+        if self.password == self.email || self.password == self.name {
+            return Err("password must differ from email and name");
+        }
+
+        Ok(())
+    }
+}
+
+const BAD_CREDENTIALS: &str = "invalid email or password";
+
+// Emails match whatever their case, the Db and the limits see them in lowercase
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+/// Failed logins in a row that lock an account.
+const LOCKOUT_FAILURES: i32 = 10;
+/// How long a locked account refuses logins, in seconds.
+const LOCKOUT_TTL: i64 = 15 * 60;
+
+// Session cookies stay out of scripts and other sites, and off plain http
+fn cookie(name: &str, value: &str, max_age: u64) -> String {
+    format!(
+        "{}={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age={}",
+        name, value, max_age
+    )
+}
+
+fn with_cookies(mut reply: Response, cookies: [String; 2]) -> Result<Response, warp::Rejection> {
+    for cookie in cookies {
+        let value = HeaderValue::from_str(&cookie).expect("cookie header");
+        reply.headers_mut().append(SET_COOKIE, value);
+    }
+
+    Ok(reply)
+}
+
+// The access and refresh tokens of a session
+fn session_reply(token: &str, refresh: &str) -> Result<Response, warp::Rejection> {
+    let cookies = [
+        cookie("token", token, ACCESS_TOKEN_TTL),
+        cookie("refresh", refresh, REFRESH_TOKEN_TTL),
+    ];
     let response = UserAuthReply {
         token: token.to_owned(),
     };
-    let reply_body = warp::reply::json(&response);
-    let with_token = warp::reply::with_header(
-        reply_body,
-        "Set-Cookie",
-        format!("token={}; HttpOnly", token),
-    );
+    with_cookies(warp::reply::json(&response).into_response(), cookies)
+}
+
+// No session, the browser drops both cookies
+fn no_session_cookies() -> [String; 2] {
+    [cookie("token", "", 0), cookie("refresh", "", 0)]
+}
+
+fn unauthorized_reply(error: &str) -> Result<Response, warp::Rejection> {
+    with_cookies(
+        error_reply(StatusCode::UNAUTHORIZED, error),
+        no_session_cookies(),
+    )
+}
+
+async fn start_session(
+    token_secret: MasterTokenSecret,
+    db: &Db,
+    id: IdType,
+    email: String,
+    name: String,
+) -> Result<Response, warp::Rejection> {
+    let (sid, refresh) = session::start(db, id).await?;
+    let claim = UserCtx::new(id, email, name, sid);
+    let token = jwt::from_utx(&claim, token_secret).await;
+    println!("\n--> session started for user {}", id);
 
-    Ok(with_token)
+    session_reply(&token, &refresh)
 }
 
 pub async fn signup(
@@ -42,56 +137,148 @@ pub async fn signup(
     db: Db,
     user: UserSignup,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    println!("-<>-<>-<>- user_signup ${:?}", user);
-    let hash = hash_password(&user.password);
-    let result = UserMac::create(&db, &user.name, &user.email, &hash).await?;
-    println!("\n--> result {:?}", result);
-
-    let claim = UserCtx {
-        id: result,
-        email: user.email,
-        name: user.name,
-        exp: std::u64::MAX as usize, // set exp claim to maximum value of usize
+    println!("-<>-<>-<>- user_signup {} {}", user.name, user.email);
+    if let Err(e) = user.validate() {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, e));
+    }
+    let email = normalize_email(&user.email);
+    let hash = hash_password(&user.password).await;
+    let result = match UserMac::create(&db, user.name.trim(), &email, &hash).await {
+        Ok(result) => result,
+        Err(e) if e.is_unique_violation() => {
+            return Ok(error_reply(
+                StatusCode::CONFLICT,
+                "email already registered",
+            ));
+        }
+        Err(e) => return Err(warp::reject::custom(e)),
     };
-    let token = jwt::from_utx(&claim, token_secret).await;
-    println!("\n--> token {:?}", token);
+    println!("\n--> result {:?}", result);
 
-    token_reply(&token)
+    let name = user.name.trim().to_owned();
+    start_session(token_secret, &db, result, email, name).await
 }
 
 pub async fn login(
     token_secret: MasterTokenSecret,
     db: Db,
+    account_limit: RateLimiter,
     user: UserLogin,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    println!("-<>-<>-<>- user_login ${:?}", user);
-    let unauthorized_token = "unauthorized";
-
-    let result = UserMac::get_by_email(&db, &user.email).await;
-    if result.is_err() {
-        return token_reply(unauthorized_token);
+    println!("-<>-<>-<>- user_login {}", user.email);
+    let email = normalize_email(&user.email);
+    if let Err(wait) = account_limit.check(&email) {
+        return Err(warp::reject::custom(TooManyRequests::after(wait)));
     }
-    let result = result.unwrap();
-    println!("\n--> result {:?}", result);
 
-    if result.is_none() {
-        return token_reply(unauthorized_token);
+    // Unknown emails cost the same verification and lockout queries, wrong
+    // passwords and locked accounts look the same
+    let now = Utc::now().timestamp();
+    let lockout = LockoutMac::get(&db, &email).await?;
+    let result = UserMac::get_by_email(&db, &email).await?;
+    println!("\n--> result {:?}", result.as_ref().map(|user| user.id));
+    let verified = match &result {
+        Some(result) => verify_password(&user.password, &result.hash).await,
+        None => {
+            verify_nothing(&user.password).await;
+            Verified::Invalid
+        }
+    };
+    if lockout.as_ref().is_some_and(|l| l.locked_until > now) {
+        return unauthorized_reply(BAD_CREDENTIALS);
     }
-    let result = result.unwrap();
-
-    let hash = hash_password(&user.password);
-    if hash != result.hash {
-        return token_reply(unauthorized_token);
+    let result = match (result, verified) {
+        (Some(result), Verified::Valid) => result,
+        (Some(result), Verified::Legacy) => {
+            // upgrade to Argon2id in place, the login goes on regardless
+            let hash = hash_password(&user.password).await;
+            if let Err(e) = UserMac::update_hash(&db, result.id, &hash).await {
+                eprintln!("user {} rehash error {:?}", result.id, e);
+            }
+            result
+        }
+        _ => {
+            let locked_until =
+                LockoutMac::fail(&db, &email, now, LOCKOUT_FAILURES, LOCKOUT_TTL).await?;
+            if locked_until > now {
+                println!("\n--> {} locked out", email);
+            }
+            return unauthorized_reply(BAD_CREDENTIALS);
+        }
+    };
+    if lockout.is_some() {
+        LockoutMac::clear(&db, &email).await?;
     }
 
-    let claim = UserCtx {
-        id: result.id,
-        email: result.email,
-        name: result.name,
-        exp: std::u64::MAX as usize, // set exp claim to maximum value of usize
+    start_session(token_secret, &db, result.id, result.email, result.name).await
+}
+
+pub async fn refresh(
+    token_secret: MasterTokenSecret,
+    db: Db,
+    refresh: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let refresh = match refresh {
+        Some(refresh) => refresh,
+        None => return unauthorized_reply("no session"),
     };
+    let (uid, sid, refresh) = match session::refresh(&db, &refresh).await {
+        Ok(next) => next,
+        Err(session::Error::Model(e)) => return Err(warp::reject::custom(e)),
+        Err(e) => {
+            println!("\n--> refresh rejected {}", e);
+            return unauthorized_reply(&e.to_string());
+        }
+    };
+    let user = match UserMac::get(&db, uid).await? {
+        Some(user) => user,
+        None => return unauthorized_reply("no such user"),
+    };
+    let claim = UserCtx::new(user.id, user.email, user.name, sid);
     let token = jwt::from_utx(&claim, token_secret).await;
-    println!("\n--> token {:?}", token);
 
-    token_reply(&token)
+    session_reply(&token, &refresh)
+}
+
+pub async fn logout(db: Db, refresh: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(refresh) = refresh {
+        match session::end(&db, &refresh).await {
+            Ok(()) => (),
+            Err(session::Error::Model(e)) => return Err(warp::reject::custom(e)),
+            Err(e) => println!("\n--> logout of no session {}", e),
+        }
+    }
+
+    with_cookies(StatusCode::NO_CONTENT.into_response(), no_session_cookies())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserSignup;
+
+    fn signup(name: &str, email: &str, password: &str) -> Result<(), &'static str> {
+        let user = UserSignup {
+            name: name.to_owned(),
+            email: email.to_owned(),
+            password: password.to_owned(),
+        };
+        user.validate()
+    }
+
+    #[test]
+    fn auth_signup_validate() {
+        assert!(signup("Some One", "someone@out.there", "password").is_ok());
+        assert!(signup(" ", "someone@out.there", "password").is_err());
+        assert!(signup(&"x".repeat(33), "someone@out.there", "password").is_err());
+        for email in [
+            "someone",
+            "@out.there",
+            "someone@there",
+            "some one@out.there",
+        ] {
+            assert_eq!(signup("Some One", email, "password"), Err("invalid email"));
+        }
+        assert!(signup("Some One", "someone@out.there", "passwor").is_err());
+        assert!(signup("password", "someone@out.there", "password").is_err());
+    }
 }
@@ -1,10 +1,18 @@
+use super::session::{self, ACCESS_TOKEN_TTL};
 use super::UserCtx;
 use crate::model::db::Db;
+use crate::model::key_rotations::KeyRotationMac;
 use crate::model::keys::KeyMac;
-use crate::model::Error as ModelError;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use crate::model::{Error as ModelError, IdType};
+use chrono::Utc;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
 use rand::Rng;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error as ThisError;
 use tokio::sync::RwLock;
 use warp::Rejection;
@@ -21,15 +29,53 @@ impl From<Vec<u8>> for TokenSecret {
     }
 }
 
-pub type MasterTokenSecret = Arc<RwLock<TokenSecret>>;
+/// How often the signing key is replaced, in seconds.
+pub const KEY_ROTATION_PERIOD: u64 = 7 * 24 * 3600;
+
+/// A signing key from the `keys` table, tokens name it by id in their `kid`.
+#[derive(Debug, Clone)]
+pub struct SigningKey {
+    pub id: IdType,
+    pub secret: TokenSecret,
+    pub created: i64,         // unix seconds, 0 if it predates rotation
+    pub retires: Option<i64>, // unix seconds, its tokens are refused after
+}
+
+/// The keys tokens are accepted from, oldest first, the newest one signs.
+#[derive(Default, Debug, Clone)]
+pub struct KeyRing(Vec<SigningKey>);
+
+impl KeyRing {
+    pub fn signing(&self) -> Option<&SigningKey> {
+        self.0.last()
+    }
+
+    // The key named by a token's kid, unless retired
+    fn verifying(&self, kid: &str, now: i64) -> Option<&SigningKey> {
+        let id = kid.parse::<IdType>().ok()?;
+        self.0
+            .iter()
+            .find(|k| k.id == id && k.retires.is_none_or(|at| at > now))
+    }
+
+    pub fn ids(&self) -> Vec<IdType> {
+        self.0.iter().map(|k| k.id).collect()
+    }
+}
+
+pub type MasterTokenSecret = Arc<RwLock<KeyRing>>;
 
 async fn parse_jwt(
     jwt: &str,
     secret: MasterTokenSecret,
 ) -> Result<UserCtx, jsonwebtoken::errors::Error> {
-    let s = secret.read().await;
-    println!("parse_jwt secret {:?} jwt {}", s, jwt);
-    let decoding_key = DecodingKey::from_secret(&s.0);
+    let kid = decode_header(jwt)?.kid.ok_or(ErrorKind::InvalidToken)?;
+    let ring = secret.read().await;
+    let key = ring
+        .verifying(&kid, Utc::now().timestamp())
+        .ok_or(ErrorKind::InvalidToken)?;
+    println!("parse_jwt kid {}", kid);
+    let decoding_key = DecodingKey::from_secret(&key.secret.0);
 
     decode::<UserCtx>(jwt, &decoding_key, &Validation::new(Algorithm::HS256))
         .map(|data| data.claims)
@@ -41,23 +87,33 @@ pub enum Error {
     FromModelError(#[from] ModelError),
 }
 
-pub async fn to_utx(token: &str, secret: MasterTokenSecret) -> Result<UserCtx, Rejection> {
-    match parse_jwt(token, secret).await {
-        Ok(utx) => {
-            println!("--> with_utx token: {} utx {:?}", token, &utx);
+pub async fn to_utx(token: &str, secret: MasterTokenSecret, db: &Db) -> Result<UserCtx, Rejection> {
+    let utx = match parse_jwt(token, secret).await {
+        Ok(utx) => utx,
+        Err(_ex) => {
+            println!("--> with_utx invalid token");
+            return Err(warp::reject::not_found());
+        }
+    };
+    match session::is_active(db, &utx.sid).await {
+        Ok(true) => {
+            println!("--> with_utx utx {:?}", &utx);
             Ok(utx)
         }
-        Err(_ex) => {
-            println!("--> with_utx invalid token: {}", token);
+        Ok(false) => {
+            println!("--> with_utx revoked session: {}", utx.sid);
             Err(warp::reject::not_found())
         }
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
 pub async fn from_utx(claim: &UserCtx, secret: MasterTokenSecret) -> String {
-    let s = secret.read().await.to_owned();
-    let header = Header::new(Algorithm::HS256);
-    let encoding_key = EncodingKey::from_secret(&s.0);
+    let ring = secret.read().await;
+    let key = ring.signing().expect("a signing key");
+    let mut header = Header::new(Algorithm::HS256);
+    header.kid = Some(key.id.to_string());
+    let encoding_key = EncodingKey::from_secret(&key.secret.0);
     encode(&header, &claim, &encoding_key).unwrap()
 }
 
@@ -65,20 +121,87 @@ pub fn random_key() -> [u8; 32] {
     rand::thread_rng().gen::<[u8; 32]>()
 }
 
-pub async fn current_key(db: &Db) -> Result<TokenSecret, Error> {
-    match KeyMac::get_last(db).await {
-        Ok(Some(k)) => {
-            let token: TokenSecret = k.key.into();
-            println!("\n--> found old key {:?}", token);
-            Ok(token)
-        }
-        _ => {
-            let new_key = random_key();
-            let result = KeyMac::create(db, &new_key).await?;
-            println!("\n--> create new key {:?} {:?}", new_key, result);
-            let token = TokenSecret(new_key);
+// A fresh key, the newest and so the signing one
+async fn create_key(db: &Db) -> Result<IdType, Error> {
+    let id = KeyMac::create(db, &random_key()).await?;
+    KeyRotationMac::create(db, id, Utc::now().timestamp()).await?;
+    println!("\n--> create new key {}", id);
+
+    Ok(id)
+}
+
+// The keys not yet retired
+async fn read_keys(db: &Db) -> Result<Vec<SigningKey>, Error> {
+    let rotations: HashMap<_, _> = KeyRotationMac::list(db)
+        .await?
+        .into_iter()
+        .map(|r| (r.key, r))
+        .collect();
+    let keys = KeyMac::list(db).await?;
+    let newest = keys.last().map(|k| k.id);
+    let now = Utc::now().timestamp();
+    let ring: Vec<SigningKey> = keys
+        .into_iter()
+        .filter_map(|k| {
+            let (created, retires) = match rotations.get(&k.id) {
+                Some(r) => (r.created, r.retires),
+                // before rotation only the last key was ever used
+                None if Some(k.id) == newest => (0, None),
+                None => return None,
+            };
+            if retires.is_some_and(|at| at <= now) {
+                return None;
+            }
+            Some(SigningKey {
+                id: k.id,
+                secret: k.key.into(),
+                created,
+                retires,
+            })
+        })
+        .collect();
+
+    Ok(ring)
+}
+
+/// The keys not yet retired, creating the first one if there are none.
+pub async fn load_keys(db: &Db) -> Result<KeyRing, Error> {
+    let mut ring = read_keys(db).await?;
+    if ring.is_empty() {
+        create_key(db).await?;
+        ring = read_keys(db).await?;
+    }
+    let ring = KeyRing(ring);
+    println!("\n--> loaded keys {:?}", ring.ids());
+
+    Ok(ring)
+}
+
+/// Signs with a new key from now on, the previous ones keep verifying the
+/// tokens they signed until those expire.
+pub async fn rotate_keys(db: &Db, secret: &MasterTokenSecret) -> Result<(), Error> {
+    let old = load_keys(db).await?;
+    let id = create_key(db).await?;
+    let retires = Utc::now().timestamp() + ACCESS_TOKEN_TTL as i64;
+    for key in old.0.iter().filter(|k| k.id != id) {
+        KeyRotationMac::retire(db, key.id, retires).await?;
+    }
+    *secret.write().await = load_keys(db).await?;
+
+    Ok(())
+}
 
-            Ok(token)
+/// Rotates the signing key every `KEY_ROTATION_PERIOD`, counting from when
+/// the current one was created.
+pub async fn rotate_keys_periodically(db: Db, secret: MasterTokenSecret) {
+    loop {
+        let created = secret.read().await.signing().map_or(0, |k| k.created);
+        let due = created + KEY_ROTATION_PERIOD as i64;
+        let wait = (due - Utc::now().timestamp()).max(0) as u64;
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+        if let Err(e) = rotate_keys(&db, &secret).await {
+            eprintln!("key rotation error {:?}", e);
+            tokio::time::sleep(Duration::from_secs(60)).await;
         }
     }
 }
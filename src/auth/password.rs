@@ -0,0 +1,103 @@
+use super::md5;
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use std::sync::OnceLock;
+use subtle::ConstantTimeEq;
+
+/// Outcome of checking a password against a stored hash.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verified {
+    Valid,
+    Legacy, // valid, but an MD5 hash to be replaced
+    Invalid,
+}
+
+/// Argon2id hash with a random salt, as a PHC string.
+pub async fn hash_password(password: &str) -> String {
+    let password = password.to_owned();
+    blocking(move || hash(&password)).await
+}
+
+/// Checks a password against an Argon2id PHC string or a legacy MD5 hash,
+/// comparing in constant time either way.
+pub async fn verify_password(password: &str, hash: &str) -> Verified {
+    let (password, hash) = (password.to_owned(), hash.to_owned());
+    blocking(move || verify(&password, &hash)).await
+}
+
+/// Verifies `password` against a throwaway hash, so an unknown account takes
+/// as long to refuse as a wrong password.
+pub async fn verify_nothing(password: &str) {
+    let password = password.to_owned();
+    blocking(move || verify_dummy(&password)).await
+}
+
+// Argon2 is slow on purpose, it runs off the async workers
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .expect("password hashing task")
+}
+
+fn hash(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("default Argon2 params")
+        .to_string()
+}
+
+// Unsalted MD5 hex, stored before Argon2id
+fn is_legacy(hash: &str) -> bool {
+    hash.len() == 32 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn verify_dummy(password: &str) {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    let dummy = DUMMY.get_or_init(|| hash("dummy password"));
+    let _ = verify(password, dummy);
+}
+
+fn verify(password: &str, hash: &str) -> Verified {
+    if is_legacy(hash) {
+        let legacy = md5::hash_password(password);
+        if bool::from(legacy.as_bytes().ct_eq(hash.as_bytes())) {
+            return Verified::Legacy;
+        }
+        // as slow as a wrong password of an Argon2id account
+        verify_dummy(password);
+        return Verified::Invalid;
+    }
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return Verified::Invalid,
+    };
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Verified::Valid,
+        Err(_) => Verified::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn auth_password_argon2id() {
+        let hash = hash_password("password").await;
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password("password").await, "salted per hash");
+        assert_eq!(verify_password("password", &hash).await, Verified::Valid);
+        assert_eq!(verify_password("passord", &hash).await, Verified::Invalid);
+        let invalid = verify_password("password", "$argon2id$").await;
+        assert_eq!(invalid, Verified::Invalid);
+    }
+
+    #[test]
+    fn auth_password_legacy_md5() {
+        let hash = md5::hash_password("password");
+        assert_eq!(hash, "5f4dcc3b5aa765d61d8327deb882cf99");
+        assert_eq!(verify("password", &hash), Verified::Legacy);
+        assert_eq!(verify("passord", &hash), Verified::Invalid);
+    }
+}
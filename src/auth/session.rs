@@ -0,0 +1,151 @@
+use crate::model::db::Db;
+use crate::model::session_secrets::SessionSecretMac;
+use crate::model::sessions::SessionMac;
+use crate::model::{Error as ModelError, IdType};
+use chrono::Utc;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use thiserror::Error as ThisError;
+use uuid::Uuid;
+use warp::reject::Reject;
+
+/// Lifetime of an access token, in seconds.
+pub const ACCESS_TOKEN_TTL: u64 = 15 * 60;
+/// Lifetime of a refresh token, in seconds, each refresh starts it over.
+pub const REFRESH_TOKEN_TTL: u64 = 30 * 24 * 3600;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+    #[error("invalid refresh token")]
+    Invalid,
+
+    #[error("refresh token reused, session revoked")]
+    Reused,
+
+    #[error(transparent)]
+    Model(#[from] ModelError),
+}
+
+impl Reject for Error {}
+
+// Refresh tokens are "<session id>.<secret>", only the secret's hash is stored
+fn hash(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+fn new_secret() -> String {
+    let bytes = rand::thread_rng().gen::<[u8; 32]>();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn expires() -> i64 {
+    Utc::now().timestamp() + REFRESH_TOKEN_TTL as i64
+}
+
+fn parse(token: &str) -> Result<(&str, &str), Error> {
+    token.split_once('.').ok_or(Error::Invalid)
+}
+
+/// A new login session of `uid`, returns its id and first refresh token.
+pub async fn start(db: &Db, uid: IdType) -> Result<(String, String), Error> {
+    let sid = Uuid::new_v4().to_string();
+    let secret = new_secret();
+    SessionMac::create(db, &sid, uid, &hash(&secret), expires()).await?;
+    let refresh = format!("{}.{}", sid, secret);
+
+    Ok((sid, refresh))
+}
+
+/// Trades a refresh token for the next one, returns the session's uid, id and
+/// new refresh token. Presenting a token already traded revokes the session,
+/// any other unknown token is merely invalid.
+pub async fn refresh(db: &Db, token: &str) -> Result<(IdType, String, String), Error> {
+    let (sid, secret) = parse(token)?;
+    let session = SessionMac::get(db, sid).await?.ok_or(Error::Invalid)?;
+    let now = Utc::now().timestamp();
+    if session.revoked || session.expires <= now {
+        return Err(Error::Invalid);
+    }
+    let (traded, next) = (hash(secret), new_secret());
+    let expires = expires();
+    if !SessionMac::rotate(db, sid, &traded, &hash(&next), expires).await? {
+        if !SessionSecretMac::traded(db, sid, &traded).await? {
+            return Err(Error::Invalid);
+        }
+        // the owner or a thief refreshed with it already, end it for both
+        SessionMac::revoke(db, sid).await?;
+        return Err(Error::Reused);
+    }
+    SessionSecretMac::add(db, sid, &traded, expires, now).await?;
+
+    Ok((session.uid, sid.to_owned(), format!("{}.{}", sid, next)))
+}
+
+/// Logs out the session of a current refresh token.
+pub async fn end(db: &Db, token: &str) -> Result<(), Error> {
+    let (sid, secret) = parse(token)?;
+    let session = SessionMac::get(db, sid).await?.ok_or(Error::Invalid)?;
+    if !bool::from(hash(secret).as_bytes().ct_eq(session.hash.as_bytes())) {
+        return Err(Error::Invalid);
+    }
+    SessionMac::revoke(db, sid).await?;
+
+    Ok(())
+}
+
+/// Whether access tokens of session `sid` are still honoured.
+pub async fn is_active(db: &Db, sid: &str) -> Result<bool, Error> {
+    let session = SessionMac::get(db, sid).await?;
+    let now = Utc::now().timestamp();
+
+    Ok(matches!(session, Some(s) if !s.revoked && s.expires > now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::db::init_db;
+
+    #[tokio::test]
+    async fn auth_session_rotation() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let (sid, first) = start(&db, 17).await?;
+        assert!(is_active(&db, &sid).await?);
+
+        let (uid, refreshed, second) = refresh(&db, &first).await?;
+        assert_eq!((uid, refreshed.as_str()), (17, sid.as_str()));
+        assert_ne!(first, second);
+
+        // A forged token is refused, the session lives on
+        let forged = format!("{}.{}", sid, new_secret());
+        assert!(matches!(refresh(&db, &forged).await, Err(Error::Invalid)));
+        assert!(is_active(&db, &sid).await?);
+
+        // A reused token revokes the session, the newest token included
+        assert!(matches!(refresh(&db, &first).await, Err(Error::Reused)));
+        assert!(!is_active(&db, &sid).await?);
+        assert!(matches!(refresh(&db, &second).await, Err(Error::Invalid)));
+        assert!(matches!(
+            refresh(&db, "nonsense").await,
+            Err(Error::Invalid)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn auth_session_end() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let (sid, token) = start(&db, 17).await?;
+        let forged = format!("{}.{}", sid, new_secret());
+        assert!(matches!(end(&db, &forged).await, Err(Error::Invalid)));
+        assert!(is_active(&db, &sid).await?);
+
+        end(&db, &token).await?;
+        assert!(!is_active(&db, &sid).await?);
+        assert!(matches!(refresh(&db, &token).await, Err(Error::Invalid)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use shakmaty::Color;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::TimeControl;
+
+/// Remaining time of both sides in milliseconds, as pushed to the players.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSnapshot {
+    pub white: u64,
+    pub black: u64,
+}
+
+/// Server side chess clock, the side to move is the one running.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    white: Duration,
+    black: Duration,
+    incr: Duration,
+    running_since: Instant,
+}
+
+impl Clock {
+    /// A clock for the time control, `None` for untimed games (main time 0)
+    /// and correspondence games, which have a deadline per move instead.
+    pub fn new(tc: &TimeControl, now: Instant) -> Option<Self> {
+        if tc.main == 0 || tc.days > 0 {
+            return None;
+        }
+        let main = Duration::from_secs(tc.main.into());
+        Some(Clock {
+            white: main,
+            black: main,
+            incr: Duration::from_secs(tc.incr.into()),
+            running_since: now,
+        })
+    }
+
+    fn stored(&self, color: Color) -> Duration {
+        match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        }
+    }
+
+    fn stored_mut(&mut self, color: Color) -> &mut Duration {
+        match color {
+            Color::White => &mut self.white,
+            Color::Black => &mut self.black,
+        }
+    }
+
+    /// Time left of `color` at `now`, `turn` is the side whose clock is running.
+    pub fn remaining(&self, color: Color, turn: Color, now: Instant) -> Duration {
+        let stored = self.stored(color);
+        if color != turn {
+            return stored;
+        }
+        stored.saturating_sub(now.saturating_duration_since(self.running_since))
+    }
+
+    /// The instant the side to move runs out of time.
+    pub fn deadline(&self, turn: Color) -> Instant {
+        self.running_since + self.stored(turn)
+    }
+
+    /// Stops the clock of `turn` after a move, adds the increment
+    /// and starts the opponent's clock. Fails if `turn` already flagged.
+    pub fn punch(&mut self, turn: Color, now: Instant) -> Result<(), Flagged> {
+        let left = self.remaining(turn, turn, now);
+        if left.is_zero() {
+            *self.stored_mut(turn) = Duration::ZERO;
+            return Err(Flagged(turn));
+        }
+        *self.stored_mut(turn) = left + self.incr;
+        self.running_since = now;
+        Ok(())
+    }
+
+    /// Restores both sides' time, e.g. on a takeback, the running clock restarts at `now`.
+    pub fn rewind(&mut self, white: Duration, black: Duration, now: Instant) {
+        self.white = white;
+        self.black = black;
+        self.running_since = now;
+    }
+
+    pub fn snapshot(&self, turn: Color, now: Instant) -> ClockSnapshot {
+        let millis = |color| self.remaining(color, turn, now).as_millis() as u64;
+        ClockSnapshot {
+            white: millis(Color::White),
+            black: millis(Color::Black),
+        }
+    }
+}
+
+/// The side which ran out of time.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Flagged(pub Color);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chess_clock_untimed() {
+        let tc = TimeControl {
+            main: 0,
+            incr: 5,
+            days: 0,
+        };
+        assert!(Clock::new(&tc, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn chess_clock_punch_increment() -> Result<(), Box<dyn std::error::Error>> {
+        let start = Instant::now();
+        let tc = TimeControl {
+            main: 60,
+            incr: 2,
+            days: 0,
+        };
+        let mut clock = Clock::new(&tc, start).unwrap();
+
+        // White thinks 10 seconds, gets 2 seconds back
+        let now = start + Duration::from_secs(10);
+        assert_eq!(
+            clock.remaining(Color::White, Color::White, now),
+            Duration::from_secs(50)
+        );
+        clock.punch(Color::White, now).unwrap();
+        let snapshot = clock.snapshot(Color::Black, now);
+        assert_eq!(snapshot.white, 52_000);
+        assert_eq!(snapshot.black, 60_000);
+
+        // Black's clock is running now, White's is stopped
+        let now = now + Duration::from_secs(30);
+        let snapshot = clock.snapshot(Color::Black, now);
+        assert_eq!(snapshot.white, 52_000);
+        assert_eq!(snapshot.black, 30_000);
+        assert_eq!(clock.deadline(Color::Black), now + Duration::from_secs(30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn chess_clock_flag() {
+        let start = Instant::now();
+        let tc = TimeControl {
+            main: 1,
+            incr: 0,
+            days: 0,
+        };
+        let mut clock = Clock::new(&tc, start).unwrap();
+
+        let now = start + Duration::from_millis(1500);
+        assert!(clock.remaining(Color::White, Color::White, now).is_zero());
+        assert_eq!(clock.punch(Color::White, now), Err(Flagged(Color::White)));
+        assert_eq!(clock.snapshot(Color::White, now).white, 0);
+    }
+
+    #[test]
+    fn chess_clock_rewind() {
+        let start = Instant::now();
+        let tc = TimeControl {
+            main: 60,
+            incr: 0,
+            days: 0,
+        };
+        let mut clock = Clock::new(&tc, start).unwrap();
+
+        let now = start + Duration::from_secs(10);
+        clock.punch(Color::White, now).unwrap();
+        let now = now + Duration::from_secs(5);
+        clock.rewind(Duration::from_secs(60), Duration::from_secs(60), now);
+        assert_eq!(
+            clock.remaining(Color::White, Color::White, now).as_secs(),
+            60
+        );
+        assert_eq!(clock.deadline(Color::White), now + Duration::from_secs(60));
+    }
+}
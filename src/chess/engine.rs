@@ -0,0 +1,498 @@
+// UCI engine child process, playing in the Hub as a regular Player
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::mpsc;
+
+use super::clock::ClockSnapshot;
+use super::hub::{LiveGameId, Message};
+use super::TimeControl;
+use crate::model::IdType;
+use crate::ws::{WsColor, WsMessage};
+
+const THINK_MARGIN: Duration = Duration::from_secs(5); // engine overhead before giving up
+const DEFAULT_MOVETIME: u64 = 1000; // ms per move of untimed games without a limit
+
+/// Engines by the name players pick them with
+pub type Engines = BTreeMap<String, EngineConfig>;
+
+/// How to start and tune an engine binary
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EngineConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub threads: Option<u32>,
+    pub hash: Option<u32>, // MB
+    #[serde(default)]
+    pub options: BTreeMap<String, String>, // UCI option presets
+    #[serde(default)]
+    pub levels: Vec<EngineLevel>, // weakest first, level 1 is the first
+}
+
+/// UCI options and search limits of a strength level
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineLevel {
+    #[serde(default)]
+    pub options: BTreeMap<String, String>, // e.g. Skill Level, UCI_LimitStrength and UCI_Elo
+    pub nodes: Option<u64>,
+    pub movetime: Option<u64>, // ms
+}
+
+impl EngineConfig {
+    /// The level settings, full strength for None, Err for an unknown level
+    pub fn level(&self, level: Option<u8>) -> Result<EngineLevel, String> {
+        match level {
+            None => Ok(EngineLevel::default()),
+            Some(n) => n
+                .checked_sub(1)
+                .and_then(|i| self.levels.get(usize::from(i)))
+                .cloned()
+                .ok_or_else(|| format!("no level {}, levels 1 to {}", n, self.levels.len())),
+        }
+    }
+
+    // setoption commands of the presets and the level
+    fn setup_commands(&self, level: &EngineLevel) -> Vec<String> {
+        let sizes = [("Threads", self.threads), ("Hash", self.hash)];
+        let sizes = sizes
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_owned(), value?.to_string())));
+        let presets = self.options.clone().into_iter();
+        sizes
+            .chain(presets)
+            .chain(level.options.clone())
+            .map(|(name, value)| format!("setoption name {} value {}", name, value))
+            .collect()
+    }
+}
+
+/// Reads the engines configuration file, JSON
+pub fn load_engines(path: &Path) -> io::Result<Engines> {
+    let data = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Engine {
+    fn spawn(config: &EngineConfig) -> io::Result<Self> {
+        let mut child = Command::new(&config.path)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+        Ok(Engine {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    async fn send(&mut self, cmd: &str) -> io::Result<()> {
+        self.stdin
+            .write_all(format!("{}\n", cmd).as_bytes())
+            .await?;
+        self.stdin.flush().await
+    }
+
+    // Skip output lines up to the one starting with `token`
+    async fn wait_for(&mut self, token: &str) -> io::Result<String> {
+        while let Some(line) = self.stdout.next_line().await? {
+            if line.split_whitespace().next() == Some(token) {
+                return Ok(line);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("engine exited waiting for {}", token),
+        ))
+    }
+
+    async fn handshake(&mut self, setup: &[String]) -> io::Result<()> {
+        self.send("uci").await?;
+        self.wait_for("uciok").await?;
+        for cmd in setup {
+            self.send(cmd).await?;
+        }
+        self.send("isready").await?;
+        self.wait_for("readyok").await?;
+        Ok(())
+    }
+
+    // None if the engine has no legal move
+    async fn best_move(&mut self, moves: &[String], go: &str) -> io::Result<Option<String>> {
+        self.send(&position_command(moves)).await?;
+        self.send(go).await?;
+        let line = self.wait_for("bestmove").await?;
+        Ok(line
+            .split_whitespace()
+            .nth(1)
+            .filter(|uci| *uci != "(none)")
+            .map(String::from))
+    }
+
+    async fn quit(mut self) {
+        let _ = self.send("quit").await;
+        let _ = tokio::time::timeout(THINK_MARGIN, self.child.wait()).await;
+    }
+}
+
+fn position_command(moves: &[String]) -> String {
+    match moves.is_empty() {
+        true => String::from("position startpos"),
+        false => format!("position startpos moves {}", moves.join(" ")),
+    }
+}
+
+// The engine stops at whichever limit comes first
+fn go_command(clock: Option<ClockSnapshot>, tc: &TimeControl, level: &EngineLevel) -> String {
+    let mut go = String::from("go");
+    if let Some(clock) = clock {
+        let incr = u64::from(tc.incr) * 1000;
+        go += &format!(
+            " wtime {} btime {} winc {} binc {}",
+            clock.white, clock.black, incr, incr
+        );
+    }
+    if let Some(nodes) = level.nodes {
+        go += &format!(" nodes {}", nodes);
+    }
+    let movetime = match (clock, level.nodes) {
+        (None, None) => Some(level.movetime.unwrap_or(DEFAULT_MOVETIME)),
+        _ => level.movetime,
+    };
+    if let Some(movetime) = movetime {
+        go += &format!(" movetime {}", movetime);
+    }
+    go
+}
+
+struct EnginePlayer {
+    engine: Engine,
+    uid: IdType,
+    tc: TimeControl,
+    setup: Vec<String>,
+    level: EngineLevel,
+    hub: mpsc::WeakUnboundedSender<Message>, // never full, the Hub may be waiting on our inbox
+    inbox: mpsc::Receiver<WsMessage>,        // what the Hub sends to the Player
+    game_id: Option<LiveGameId>,
+    color: Option<WsColor>,
+    moves: Vec<String>, // uci moves from the starting position
+    clock: Option<ClockSnapshot>,
+}
+
+impl EnginePlayer {
+    // Tracks the game, false once it is over
+    fn handle_message(&mut self, msg: WsMessage) -> bool {
+        match msg {
+            WsMessage::GameResponse { game_id, color } => {
+                self.game_id = Some(game_id);
+                self.color = Some(color);
+            }
+            WsMessage::Move { uci, .. } => self.moves.push(uci),
+            WsMessage::Clock { clock, .. } => self.clock = Some(clock),
+            WsMessage::GameState(state) => {
+                self.moves = state.moves;
+                self.clock = state.clock;
+            }
+            WsMessage::GameEnd { .. } => return false,
+            msg => println!("ENGINE {} ignores {:?}", self.uid, msg),
+        }
+        true
+    }
+
+    fn my_turn(&self) -> bool {
+        match self.color {
+            Some(color) => self.moves.len().is_multiple_of(2) == (color == WsColor::White),
+            None => false,
+        }
+    }
+
+    fn think_time(&self) -> Duration {
+        let left = match (self.clock, self.color) {
+            (Some(clock), Some(WsColor::White)) => clock.white,
+            (Some(clock), Some(WsColor::Black)) => clock.black,
+            _ => self.level.movetime.unwrap_or(DEFAULT_MOVETIME),
+        };
+        Duration::from_millis(left) + THINK_MARGIN
+    }
+
+    // Keeps reading the inbox while thinking, so that the Hub never waits on
+    // a full one. False once the game is over.
+    async fn think(&mut self) -> io::Result<bool> {
+        let go = go_command(self.clock, &self.tc, &self.level);
+        let think_time = self.think_time();
+        let (mut received, mut open) = (vec![], true);
+        let best_move = {
+            let best_move = self.engine.best_move(&self.moves, &go);
+            let best_move = tokio::time::timeout(think_time, best_move);
+            tokio::pin!(best_move);
+            loop {
+                tokio::select! {
+                    best_move = &mut best_move => break best_move,
+                    msg = self.inbox.recv(), if open => match msg {
+                        Some(WsMessage::GameEnd { .. }) => return Ok(false),
+                        Some(msg) => received.push(msg),
+                        None => open = false,
+                    },
+                }
+            }
+        };
+        let best_move = best_move
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "engine is not moving"))??;
+
+        // no move means the game is over, the Hub knows
+        if let Some(uci) = best_move {
+            self.moves.push(uci.clone());
+            if let (Some(hub), Some(game_id)) = (self.hub.upgrade(), self.game_id) {
+                let uid = self.uid;
+                let _ = hub.send(Message::Move { game_id, uci, uid });
+            }
+        }
+        Ok(received.into_iter().all(|msg| self.handle_message(msg)))
+    }
+
+    async fn play(&mut self) -> io::Result<()> {
+        self.engine.handshake(&self.setup).await?;
+        while let Some(msg) = self.inbox.recv().await {
+            if !self.handle_message(msg) {
+                return Ok(());
+            }
+            // catch up, e.g. the clock following a move
+            while let Ok(msg) = self.inbox.try_recv() {
+                if !self.handle_message(msg) {
+                    return Ok(());
+                }
+            }
+            if self.my_turn() && !self.think().await? {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    // A broken engine leaves the game as a disconnected player does
+    async fn run(mut self, own: mpsc::WeakSender<WsMessage>) {
+        if let Err(e) = self.play().await {
+            eprintln!("ENGINE {} error {}", self.uid, e);
+            if let (Some(hub), Some(respond_to)) = (self.hub.upgrade(), own.upgrade()) {
+                let uid = self.uid;
+                let _ = hub.send(Message::WsDisconnect { respond_to, uid });
+            }
+        }
+        self.engine.quit().await;
+    }
+}
+
+/// Starts the engine process for one game, returns the Player's respond_to
+pub fn spawn(
+    config: &EngineConfig,
+    level: EngineLevel,
+    tc: TimeControl,
+    uid: IdType,
+    hub: mpsc::WeakUnboundedSender<Message>,
+) -> io::Result<mpsc::Sender<WsMessage>> {
+    let engine = Engine::spawn(config)?;
+    let (sender, inbox) = mpsc::channel(8);
+    let player = EnginePlayer {
+        engine,
+        uid,
+        tc,
+        setup: config.setup_commands(&level),
+        level,
+        hub,
+        inbox,
+        game_id: None,
+        color: None,
+        moves: vec![],
+        clock: None,
+    };
+    tokio::spawn(player.run(sender.downgrade()));
+    Ok(sender)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::chess::{GameEndReason, GameResult};
+
+    /// The scripted stand-in engine, playing `moves` in order
+    pub fn fake_engine(moves: &[&str]) -> EngineConfig {
+        let script = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/chess/testdata/fake_uci.sh"
+        );
+        EngineConfig {
+            path: PathBuf::from("sh"),
+            args: std::iter::once(script)
+                .chain(moves.iter().copied())
+                .map(String::from)
+                .collect(),
+            threads: Some(1),
+            levels: vec![EngineLevel {
+                nodes: Some(1),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chess_engine_commands() {
+        assert_eq!(position_command(&[]), "position startpos");
+        let moves = [String::from("e2e4"), String::from("e7e5")];
+        assert_eq!(
+            position_command(&moves),
+            "position startpos moves e2e4 e7e5"
+        );
+
+        let tc = TimeControl {
+            main: 60,
+            incr: 2,
+            days: 0,
+        };
+        let clock = ClockSnapshot {
+            white: 59_000,
+            black: 60_000,
+        };
+        let full = EngineLevel::default();
+        assert_eq!(
+            go_command(Some(clock), &tc, &full),
+            "go wtime 59000 btime 60000 winc 2000 binc 2000"
+        );
+        assert_eq!(go_command(None, &tc, &full), "go movetime 1000");
+
+        let nodes = EngineLevel {
+            nodes: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(go_command(None, &tc, &nodes), "go nodes 100");
+        let movetime = EngineLevel {
+            movetime: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(
+            go_command(Some(clock), &tc, &movetime),
+            "go wtime 59000 btime 60000 winc 2000 binc 2000 movetime 50"
+        );
+    }
+
+    #[test]
+    fn chess_engine_levels() -> Result<(), Box<dyn std::error::Error>> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("engines.json");
+        let engines = load_engines(&path)?;
+        println!("engines {:?}", engines);
+        let config = engines.get("Sockfish").expect("configured engine");
+
+        // full strength only has the presets
+        let level = config.level(None)?;
+        let setup = config.setup_commands(&level);
+        assert!(setup.contains(&String::from("setoption name Threads value 1")));
+        assert!(!setup.iter().any(|cmd| cmd.contains("Skill Level")));
+
+        let level = config.level(Some(1))?;
+        let setup = config.setup_commands(&level);
+        assert_eq!(
+            setup.last().map(String::as_str),
+            Some("setoption name Skill Level value 0")
+        );
+
+        assert!(config.level(Some(0)).is_err());
+        assert!(config.level(Some(100)).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_engine_fake_uci() -> Result<(), Box<dyn std::error::Error>> {
+        let config = fake_engine(&["e7e5"]);
+        let mut engine = Engine::spawn(&config)?;
+        engine
+            .handshake(&config.setup_commands(&config.level(None)?))
+            .await?;
+
+        let moves = [String::from("e2e4")];
+        let best_move = engine.best_move(&moves, "go movetime 10").await?;
+        assert_eq!(best_move.as_deref(), Some("e7e5"));
+
+        // out of scripted moves
+        let best_move = engine.best_move(&moves, "go movetime 10").await?;
+        assert_eq!(best_move, None);
+        engine.quit().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_engine_inbox_while_thinking() -> Result<(), Box<dyn std::error::Error>> {
+        let config = fake_engine(&["-"]);
+        let (hub, _hub_rx) = mpsc::unbounded_channel();
+        let tc = TimeControl::default();
+        let inbox = spawn(&config, config.level(None)?, tc, -1, hub.downgrade())?;
+        let game_id = LiveGameId::new_v4();
+        let color = WsColor::White;
+        inbox
+            .send(WsMessage::GameResponse { game_id, color })
+            .await?;
+
+        // More than the inbox holds, none waits on the thinking engine
+        for count in 0..32 {
+            let msg = WsMessage::Spectators { game_id, count };
+            tokio::time::timeout(Duration::from_secs(1), inbox.send(msg)).await??;
+        }
+        let msg = WsMessage::GameEnd {
+            game_id,
+            result: GameResult::Aborted,
+            reason: GameEndReason::Aborted,
+        };
+        inbox.send(msg).await?;
+        tokio::time::timeout(Duration::from_secs(2), inbox.closed()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_engine_moves_while_hub_busy() -> Result<(), Box<dyn std::error::Error>> {
+        let config = fake_engine(&["g8f6", "f6g8"].repeat(8));
+        let (hub, mut hub_rx) = mpsc::unbounded_channel();
+        let tc = TimeControl::default();
+        let inbox = spawn(&config, config.level(None)?, tc, -1, hub.downgrade())?;
+        let game_id = LiveGameId::new_v4();
+        let color = WsColor::Black;
+        inbox
+            .send(WsMessage::GameResponse { game_id, color })
+            .await?;
+
+        // The Hub reads none of the engine's moves, more than a bounded channel holds
+        for (played, uci) in ["g1f3", "f3g1"].repeat(8).into_iter().enumerate() {
+            let msg = WsMessage::Move {
+                game_id,
+                uci: String::from(uci),
+            };
+            tokio::time::timeout(Duration::from_secs(1), inbox.send(msg)).await??;
+            let answered = async {
+                while hub_rx.len() <= played {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(2), answered).await?;
+        }
+        while let Ok(msg) = hub_rx.try_recv() {
+            assert!(matches!(msg, Message::Move { uid: -1, .. }));
+        }
+
+        Ok(())
+    }
+}
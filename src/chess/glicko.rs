@@ -0,0 +1,162 @@
+// Glicko-2 rating system - Mark E. Glickman, http://www.glicko.net/glicko/glicko2.pdf
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+const SCALE: f64 = 173.7178; // Glicko to Glicko-2 scale
+const BASE: f64 = 1500.0;
+const MAX_DEVIATION: f64 = 350.0;
+const TAU: f64 = 0.5; // constrains the volatility change
+const EPSILON: f64 = 0.000001; // volatility convergence tolerance
+
+/// Glicko-2 rating, on the familiar Glicko scale.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: BASE,
+            deviation: MAX_DEVIATION,
+            volatility: 0.06,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, mu_j: f64, phi_j: f64) -> f64 {
+    1.0 / (1.0 + (-g(phi_j) * (mu - mu_j)).exp())
+}
+
+impl Rating {
+    fn mu(&self) -> f64 {
+        (self.rating - BASE) / SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+
+    /// New rating after a rating period with the given (opponent, score) results,
+    /// score is 1 for a win, 0.5 for a draw and 0 for a loss.
+    pub fn rate(&self, results: &[(Rating, f64)]) -> Rating {
+        let (mu, phi, sigma) = (self.mu(), self.phi(), self.volatility);
+        if results.is_empty() {
+            let deviation = (phi * phi + sigma * sigma).sqrt() * SCALE;
+            return Rating {
+                deviation: deviation.min(MAX_DEVIATION),
+                ..*self
+            };
+        }
+
+        // estimated variance and improvement from the game outcomes only
+        let mut v_inv = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in results {
+            let (mu_j, phi_j) = (opponent.mu(), opponent.phi());
+            let e = expected(mu, mu_j, phi_j);
+            v_inv += g(phi_j) * g(phi_j) * e * (1.0 - e);
+            improvement += g(phi_j) * (score - e);
+        }
+        let v = 1.0 / v_inv;
+        let delta = v * improvement;
+
+        let sigma = volatility(delta, phi, v, sigma);
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * improvement;
+
+        Rating {
+            rating: mu * SCALE + BASE,
+            deviation: (phi * SCALE).min(MAX_DEVIATION),
+            volatility: sigma,
+        }
+    }
+}
+
+// New volatility, Illinois algorithm (step 5 of the paper)
+fn volatility(delta: f64, phi: f64, v: f64, sigma: f64) -> f64 {
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        let d = phi * phi + v + ex;
+        ex * (delta * delta - phi * phi - v - ex) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+    };
+
+    let mut big_a = a;
+    let mut big_b = if delta * delta > phi * phi + v {
+        (delta * delta - phi * phi - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+    let (mut f_a, mut f_b) = (f(big_a), f(big_b));
+    while (big_b - big_a).abs() > EPSILON {
+        let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+        let f_c = f(big_c);
+        if f_c * f_b <= 0.0 {
+            big_a = big_b;
+            f_a = f_b;
+        } else {
+            f_a /= 2.0;
+        }
+        big_b = big_c;
+        f_b = f_c;
+    }
+    (big_a / 2.0).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chess_glicko_paper_example() {
+        let player = rating(1500.0, 200.0);
+        let results = [
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ];
+        let rated = player.rate(&results);
+        println!("rated {:?}", rated);
+
+        assert!((rated.rating - 1464.06).abs() < 0.01);
+        assert!((rated.deviation - 151.52).abs() < 0.01);
+        assert!((rated.volatility - 0.05999).abs() < 0.00001);
+    }
+
+    #[test]
+    fn chess_glicko_single_game() {
+        let (white, black) = (Rating::default(), Rating::default());
+        let white_rated = white.rate(&[(black, 1.0)]);
+        let black_rated = black.rate(&[(white, 0.0)]);
+
+        assert!(white_rated.rating > 1500.0);
+        assert!(black_rated.rating < 1500.0);
+        assert!((white_rated.rating - 1500.0 - (1500.0 - black_rated.rating)).abs() < 0.01);
+        assert!(white_rated.deviation < 350.0);
+
+        // no games, deviation grows back
+        let idle = white_rated.rate(&[]);
+        assert!(idle.deviation > white_rated.deviation);
+        assert_eq!(idle.rating, white_rated.rating);
+    }
+}
@@ -1,13 +1,28 @@
 #![allow(dead_code)]
 // Recipe Keynote | Actors with Tokio – a lesson in ownership - Alice Ryhl
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
+use super::clock::Clock;
+use super::engine::{self, Engines};
+use super::glicko::Rating;
+use super::pgn::{PgnGame, PlayedMove};
 use super::*;
 use crate::chess::uci::UciMove;
+use crate::model::chats::ChatMac;
+use crate::model::correspondence::{self, CorrespondenceMac};
+use crate::model::db::Db;
+use crate::model::games::GameMac;
+use crate::model::ratings::RatingMac;
+use crate::model::users::UserMac;
 use crate::model::IdType;
 use crate::ws::*;
-use shakmaty::Chess;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{fen::Fen, CastlingMode, Chess, EnPassantMode, Position};
+use std::time::Duration;
+use tokio::time::Instant;
 use tokio::{io, sync::mpsc};
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub enum Message {
@@ -15,35 +30,586 @@ pub enum Message {
         msg: GamePreference,
         respond_to: mpsc::Sender<WsMessage>, // handle to user's Ws Tx
         uid: IdType,                         // user Db Id
+        name: String,                        // user display name
     },
     Move {
+        game_id: LiveGameId,
         uci: String,
         uid: IdType, // user Db Id
     },
+    WsConnect {
+        respond_to: mpsc::Sender<WsMessage>, // handle to user's new Ws Tx
+        uid: IdType,
+    },
+    GetGameState {
+        uid: IdType,
+    },
     WsDisconnect {
+        respond_to: mpsc::Sender<WsMessage>, // handle to user's closed Ws Tx
+        uid: IdType,
+    },
+    FlagCheck {
+        game_id: LiveGameId, // scheduled by the Hub itself
+    },
+    GraceExpired {
+        uid: IdType,
+        since: Instant, // scheduled by the Hub itself
+    },
+    Matchmake, // scheduled by the Hub itself, while rated requests wait
+    RatingsLoaded {
+        id: RatingLoadId,             // of the requests waiting for them
+        ratings: Vec<Option<Rating>>, // in request order, None if unrated
+    },
+    GetRatings {
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+    },
+    GetEngines {
+        respond_to: mpsc::Sender<WsMessage>,
+    },
+    Resign {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    OfferDraw {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    AcceptDraw {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    DeclineDraw {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    Abort {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    TakebackRequest {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    TakebackAccept {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    TakebackDecline {
+        game_id: LiveGameId,
+        uid: IdType,
+    },
+    RematchOffer {
+        game_id: LiveGameId, // the finished game
+        uid: IdType,
+    },
+    RematchAccept {
+        game_id: LiveGameId, // the finished game
+        uid: IdType,
+    },
+    RematchExpired {
+        game_id: LiveGameId, // the finished game
+        since: Instant,      // scheduled by the Hub itself
+    },
+    Spectate {
+        game_id: LiveGameId,
+        respond_to: mpsc::Sender<WsMessage>, // handle to spectator's Ws Tx
+        uid: IdType,
+    },
+    Unspectate {
+        game_id: LiveGameId,
+        respond_to: mpsc::Sender<WsMessage>,
+    },
+    Lobby {
+        respond_to: mpsc::Sender<WsMessage>, // handle to subscriber's Ws Tx
+        uid: IdType,
+    },
+    LeaveLobby {
+        respond_to: mpsc::Sender<WsMessage>,
+    },
+    AcceptSeek {
+        id: SeekId,
+        respond_to: mpsc::Sender<WsMessage>, // handle to user's Ws Tx
+        uid: IdType,
+        name: String,
+    },
+    Challenge {
+        to_user: IdType,
+        msg: GamePreference,
+        respond_to: mpsc::Sender<WsMessage>, // handle to challenger's Ws Tx
         uid: IdType,
+        name: String,
+    },
+    ChallengeAccept {
+        id: ChallengeId,
+        respond_to: mpsc::Sender<WsMessage>, // handle to user's Ws Tx
+        uid: IdType,
+        name: String,
+    },
+    ChallengeDecline {
+        id: ChallengeId,
+        uid: IdType,
+    },
+    ChallengeCancel {
+        id: ChallengeId,
+        uid: IdType,
+    },
+    ChallengeExpired {
+        id: ChallengeId, // scheduled by the Hub itself
+    },
+    ChallengeChecked {
+        id: ChallengeId, // by the Hub itself, once the Db knows the challenged user
+        exists: bool,
+    },
+    Chat {
+        room: ChatRoom,
+        text: String,
+        respond_to: mpsc::Sender<WsMessage>, // handle to sender's Ws Tx
+        uid: IdType,
+        name: String,
+    },
+    Mute {
+        uid: IdType,
+        muted: IdType, // whose chat lines uid no longer gets, while uid is connected
+    },
+    Unmute {
+        uid: IdType,
+        muted: IdType,
+    },
+    ChatWindowExpired {
+        uid: IdType, // scheduled by the Hub itself, after each chat line
     },
+    RestoreCorrespondence {
+        games: Vec<correspondence::Model>, // stored before a restart
+    },
+    #[cfg(test)]
+    CheckMembers {
+        respond_to: tokio::sync::oneshot::Sender<Result<(), String>>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct HubConfig {
+    pub reconnect_grace: Duration, // time to come back before losing the game
+    pub rating_window: f64,        // initial max rating difference to pair
+    pub rating_window_growth: f64, // window widening per second of waiting
+    pub matchmaking_tick: Duration, // pairing retry period of waiting requests
+    pub engines: Engines,          // from the configuration file
+    pub rematch_timeout: Duration, // rematch offers and finished games expire
+    pub challenge_timeout: Duration, // unanswered challenges expire
+    pub challenge_limit: usize,    // pending challenges per challenger
+    pub chat_max_len: usize,       // characters per chat line
+    pub chat_limit: usize,         // chat lines per user within chat_window
+    pub chat_window: Duration,
+    pub mute_limit: usize, // users muted per user
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        HubConfig {
+            reconnect_grace: Duration::from_secs(60),
+            rating_window: 100.0,
+            rating_window_growth: 10.0,
+            matchmaking_tick: Duration::from_secs(1),
+            engines: Engines::new(),
+            rematch_timeout: Duration::from_secs(60),
+            challenge_timeout: Duration::from_secs(600),
+            challenge_limit: 10,
+            chat_max_len: 280,
+            chat_limit: 5,
+            chat_window: Duration::from_secs(10),
+            mute_limit: 100,
+        }
+    }
+}
+
+impl HubConfig {
+    // Max rating difference accepted by a request waiting since `since`
+    fn rating_window(&self, since: Instant, now: Instant) -> f64 {
+        let waited = now.saturating_duration_since(since).as_secs_f64();
+        self.rating_window + self.rating_window_growth * waited
+    }
 }
 
+// A user playing one or more live games, on its newest connection
 struct Player {
+    uid: IdType,
+    name: String,
+    respond_to: mpsc::Sender<WsMessage>,
+    away_since: Option<Instant>, // Ws disconnected
+}
+
+impl Player {
+    fn new(uid: IdType, name: String, respond_to: mpsc::Sender<WsMessage>) -> Self {
+        Player {
+            uid,
+            name,
+            respond_to,
+            away_since: None,
+        }
+    }
+}
+
+// Which users play which live games, both ways
+#[derive(Default)]
+struct Members {
+    games: HashMap<IdType, Vec<LiveGameId>>,   // uid -> game ids
+    players: HashMap<LiveGameId, [IdType; 2]>, // game id -> white, black
+}
+
+impl Members {
+    fn join(&mut self, game_id: LiveGameId, uids: [IdType; 2]) {
+        for uid in uids {
+            self.games.entry(uid).or_default().push(game_id);
+        }
+        self.players.insert(game_id, uids);
+    }
+
+    // Removes a game, returns the users left without any
+    fn leave(&mut self, game_id: LiveGameId) -> Vec<IdType> {
+        let mut idle = vec![];
+        for uid in self.players.remove(&game_id).unwrap_or_default() {
+            if let Some(games) = self.games.get_mut(&uid) {
+                games.retain(|id| *id != game_id);
+                if games.is_empty() {
+                    self.games.remove(&uid);
+                    idle.push(uid);
+                }
+            }
+        }
+        idle
+    }
+
+    fn games(&self, uid: IdType) -> Vec<LiveGameId> {
+        self.games.get(&uid).cloned().unwrap_or_default()
+    }
+}
+
+// A Ws connection following a game or the lobby
+struct Subscriber {
     uid: IdType,
     respond_to: mpsc::Sender<WsMessage>,
-    color: WsColor,
-    opponent: IdType,
 }
 
-type LiveGameId = (IdType, IdType);
+impl Subscriber {
+    fn same_channel(&self, other: &mpsc::Sender<WsMessage>) -> bool {
+        self.respond_to.same_channel(other)
+    }
+}
+
+// Without waiting, subscribers too slow to take msg or gone are dropped
+fn send_subscribers(
+    subscribers: &mut Vec<Subscriber>,
+    msg: &WsMessage,
+    to: impl Fn(&Subscriber) -> bool,
+) {
+    subscribers.retain(|subscriber| {
+        if !to(subscriber) {
+            return true;
+        }
+        match subscriber.respond_to.try_send(msg.clone()) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("HUB subscriber {} dropped, {}", subscriber.uid, e);
+                false
+            }
+        }
+    });
+}
+
+pub type LiveGameId = Uuid;
 struct LiveGame {
+    id: LiveGameId,
     game: Chess,
     tc: TimeControl,
+    clock: Option<Clock>,            // None for untimed and correspondence games
+    deadline: Option<DateTime<Utc>>, // correspondence, for the side to move
     white: IdType,
     black: IdType,
+    positions: HashMap<Zobrist64, u32>, // occurrences, for threefold repetition
+    moves: Vec<PlayedMove>,
+    date: NaiveDate, // game start, UTC
+    rated: bool,
+    draw_offer: Option<Color>,   // the side with an outstanding draw offer
+    takebacks: bool,             // allowed by both players
+    takeback: Option<Color>,     // the side asking to take its last move back
+    spectators: Vec<Subscriber>, // read-only, besides their own chat room
+    chat: Vec<ChatLine>,         // players' and spectators' rooms, stored with the game
+}
+
+impl LiveGame {
+    fn new(tc: TimeControl, white: IdType, black: IdType, now: Instant) -> Self {
+        let mut live_game = LiveGame {
+            id: Uuid::new_v4(),
+            game: Chess::default(),
+            tc,
+            clock: Clock::new(&tc, now),
+            deadline: None,
+            white,
+            black,
+            positions: HashMap::new(),
+            moves: vec![],
+            date: Utc::now().date_naive(),
+            rated: false,
+            draw_offer: None,
+            takebacks: false,
+            takeback: None,
+            spectators: vec![],
+            chat: vec![],
+        };
+        live_game.record_position();
+        live_game.renew_deadline();
+        live_game
+    }
+
+    // A correspondence game stored in the Db, None if it can't be replayed
+    fn restore(stored: &correspondence::Model, now: Instant) -> Option<Self> {
+        let tc = TimeControl {
+            days: stored.days.try_into().ok()?,
+            ..TimeControl::default()
+        };
+        tc.validate().ok()?;
+        let mut live_game = LiveGame::new(tc, stored.white, stored.black, now);
+        live_game.id = stored.id.parse().ok()?;
+        live_game.date = NaiveDate::parse_from_str(&stored.date, "%Y-%m-%d").ok()?;
+        live_game.rated = stored.rated;
+        live_game.takebacks = stored.takebacks;
+        for uci in stored.moves.split_whitespace() {
+            let m = live_game.game.make_move(uci).ok()?;
+            live_game.moves.push(PlayedMove { m, clock: None });
+            live_game.record_position();
+        }
+        live_game.deadline = Some(Utc.timestamp_opt(stored.deadline, 0).single()?);
+        Some(live_game)
+    }
+
+    // The Db row of a correspondence game, None for other games
+    fn stored(&self, [white_name, black_name]: [String; 2]) -> Option<correspondence::Model> {
+        Some(correspondence::Model {
+            id: self.id.to_string(),
+            white: self.white,
+            black: self.black,
+            white_name,
+            black_name,
+            days: self.tc.days()?.try_into().ok()?,
+            rated: self.rated,
+            takebacks: self.takebacks,
+            date: self.date.format("%Y-%m-%d").to_string(),
+            moves: self.uci_moves().join(" "),
+            deadline: self.deadline?.timestamp(),
+        })
+    }
+
+    // A correspondence move is due within the days per move from now
+    fn renew_deadline(&mut self) {
+        if let Some(days) = self.tc.days() {
+            let days = chrono::Duration::days(days.into());
+            self.deadline = Utc::now().checked_add_signed(days);
+        }
+    }
+
+    // The side to move ran out of its clock time or its correspondence deadline
+    fn out_of_time(&self, now: Instant) -> bool {
+        let turn = self.game.turn();
+        match (&self.clock, self.deadline) {
+            (Some(clock), _) => clock.remaining(turn, turn, now).is_zero(),
+            (None, Some(deadline)) => deadline <= Utc::now(),
+            (None, None) => false,
+        }
+    }
+
+    // Color and opponent of uid, None if it doesn't play the game
+    fn seat(&self, uid: IdType) -> Option<(Color, IdType)> {
+        if uid == self.white {
+            Some((Color::White, self.black))
+        } else if uid == self.black {
+            Some((Color::Black, self.white))
+        } else {
+            None
+        }
+    }
+
+    fn uci_moves(&self) -> Vec<String> {
+        let moves = self.moves.iter();
+        moves
+            .map(|played| played.m.to_uci(CastlingMode::Standard).to_string())
+            .collect()
+    }
+
+    fn send_spectators(&mut self, msg: WsMessage) {
+        send_subscribers(&mut self.spectators, &msg, |_| true);
+    }
+
+    fn position_hash(&self) -> Zobrist64 {
+        self.game.zobrist_hash(EnPassantMode::Legal)
+    }
+
+    fn record_position(&mut self) {
+        *self.positions.entry(self.position_hash()).or_default() += 1;
+    }
+
+    // Result and reason if the game is over in the current position
+    fn termination(&self) -> Option<(GameResult, GameEndReason)> {
+        let game = &self.game;
+        if let Some(outcome) = game.outcome() {
+            let reason = if game.is_checkmate() {
+                GameEndReason::Checkmate
+            } else if game.is_stalemate() {
+                GameEndReason::Stalemate
+            } else {
+                GameEndReason::InsufficientMaterial
+            };
+            return Some((outcome.into(), reason));
+        }
+        if self.positions.get(&self.position_hash()) >= Some(&3) {
+            return Some((GameResult::Draw, GameEndReason::ThreefoldRepetition));
+        }
+        if game.halfmoves() >= 100 {
+            return Some((GameResult::Draw, GameEndReason::FiftyMoveRule));
+        }
+        None
+    }
+
+    // Plies to undo so that `color` can replay its last move, None without one
+    fn takeback_plies(&self, color: Color) -> Option<usize> {
+        let plies = if self.game.turn() == color { 2 } else { 1 };
+        (self.moves.len() >= plies).then_some(plies)
+    }
+
+    // Undo the last plies, replaying the remaining moves from the start,
+    // each side gets back the time it had after its last remaining move
+    fn take_back(&mut self, plies: usize, now: Instant) {
+        self.moves.truncate(self.moves.len() - plies);
+        self.game = Chess::default();
+        self.positions.clear();
+        self.record_position();
+        let replayed: Vec<_> = self.moves.iter().map(|played| played.m.clone()).collect();
+        for m in &replayed {
+            self.game.play_unchecked(m);
+            self.record_position();
+        }
+
+        if let Some(clock) = &mut self.clock {
+            let main = Duration::from_secs(self.tc.main.into());
+            let left = |first: usize| {
+                let mut mine = self.moves.iter().skip(first).step_by(2);
+                mine.next_back()
+                    .and_then(|played| played.clock)
+                    .unwrap_or(main)
+            };
+            clock.rewind(left(0), left(1), now);
+        }
+        self.renew_deadline();
+        self.draw_offer = None;
+        self.takeback = None;
+    }
 }
 
+pub type SeekId = u64;
 struct GameRequest {
+    id: SeekId, // lobby listing id, 0 if never listed
     msg: GamePreference,
     respond_to: mpsc::Sender<WsMessage>,
     uid: IdType,
+    name: String,
+    rating: Option<Rating>, // None for unrated games
+    since: Instant,
+}
+
+impl GameRequest {
+    // Can be paired, the rating difference within the wider of both windows
+    fn pairs_with(&self, other: &GameRequest, config: &HubConfig, now: Instant) -> bool {
+        if self.uid == other.uid
+            || !self.msg.tc.matches(&other.msg.tc)
+            || self.msg.color.against(other.msg.color).is_none()
+        {
+            return false;
+        }
+        match rating_gap(self, other) {
+            Some(gap) => {
+                let window = config.rating_window(self.since.min(other.since), now);
+                gap <= window
+            }
+            None => true,
+        }
+    }
+}
+
+impl GameRequest {
+    fn seek(&self) -> Seek {
+        Seek {
+            id: self.id,
+            uid: self.uid,
+            name: self.name.clone(),
+            preference: self.msg.clone(),
+            rating: self.rating.map(|rating| rating.rating),
+        }
+    }
+}
+
+fn rating_gap(a: &GameRequest, b: &GameRequest) -> Option<f64> {
+    Some((a.rating?.rating - b.rating?.rating).abs())
+}
+
+pub type RatingLoadId = u64;
+// Requests waiting in the Hub for their ratings from the Db
+enum Rated {
+    Seek(GameRequest),
+    Pairing(GameRequest, GameRequest), // an accepted seek or challenge, older first
+}
+
+impl Rated {
+    fn requests(&self) -> Vec<&GameRequest> {
+        match self {
+            Rated::Seek(request) => vec![request],
+            Rated::Pairing(older, newer) => vec![older, newer],
+        }
+    }
+
+    fn with_ratings(self, ratings: Vec<Option<Rating>>) -> Self {
+        let mut ratings = ratings.into_iter();
+        let mut rate = |mut request: GameRequest| {
+            request.rating = ratings.next().flatten();
+            request
+        };
+        match self {
+            Rated::Seek(request) => Rated::Seek(rate(request)),
+            Rated::Pairing(older, newer) => Rated::Pairing(rate(older), rate(newer)),
+        }
+    }
+}
+
+// A finished game of two humans, open for a rematch for a while
+struct Rematch {
+    players: [Player; 2], // white and black of the finished game
+    tc: TimeControl,
+    rated: bool,
+    takebacks: bool,
+    offer: Option<IdType>, // the player offering
+    since: Instant,        // game end or offer, expires rematch_timeout later
+}
+
+pub type ChallengeId = u64;
+// A game offered to a specific user, kept until answered or expired
+struct Challenge {
+    id: ChallengeId,
+    from: IdType,
+    from_name: String,
+    respond_to: mpsc::Sender<WsMessage>, // challenger's Ws Tx when challenging
+    to: IdType,
+    msg: GamePreference,
+}
+
+impl Challenge {
+    fn info(&self) -> ChallengeInfo {
+        ChallengeInfo {
+            id: self.id,
+            from: self.from,
+            from_name: self.from_name.clone(),
+            to: self.to,
+            preference: self.msg.clone(),
+        }
+    }
 }
 
 type GameRequests = VecDeque<GameRequest>;
@@ -54,10 +620,30 @@ struct HubState {
     requests: GameRequests,
     games: LiveGames,
     players: Players,
+    members: Members,
+    matchmake_scheduled: bool,
+    last_engine_uid: IdType, // engines play under negative uids
+    rematches: HashMap<LiveGameId, Rematch>,
+    last_seek_id: SeekId,
+    lobby: Vec<Subscriber>, // lobby feed and chat
+    connections: HashMap<IdType, mpsc::Sender<WsMessage>>, // newest Ws Tx per user
+    last_challenge_id: ChallengeId,
+    challenges: HashMap<ChallengeId, Challenge>,
+    unchecked_challenges: HashMap<ChallengeId, Challenge>, // challenged user not in the Db yet
+    chat_sent: HashMap<IdType, VecDeque<Instant>>,         // recent chat lines per user
+    last_rating_load: RatingLoadId,
+    rating_loads: HashMap<RatingLoadId, Rated>, // requests waiting for Db ratings
+    mutes: HashMap<IdType, HashSet<IdType>>,    // users muted by each user
 }
 
 pub struct Hub {
     receiver: mpsc::Receiver<Message>,
+    sender: mpsc::WeakSender<Message>,       // to self, for timers
+    engines: mpsc::UnboundedSender<Message>, // engine moves, never blocking the engines
+    engine_receiver: mpsc::UnboundedReceiver<Message>,
+    db: Option<Db>, // None plays without persistence, e.g. in tests
+    writer: Option<mpsc::UnboundedSender<Write>>, // ordered Db writes
+    config: HubConfig,
 }
 
 impl Hub {
@@ -68,16 +654,254 @@ impl Hub {
                 msg,
                 respond_to,
                 uid,
+                name,
+            } => {
+                self.handle_game_preference(ctx, msg, respond_to, uid, name)
+                    .await;
+            }
+            Move { game_id, uci, uid } => {
+                self.handle_move(ctx, game_id, &uci, uid).await;
+            }
+            WsConnect { respond_to, uid } => {
+                self.handle_connect(ctx, respond_to, uid).await;
+            }
+            GetGameState { uid } => {
+                for game_id in ctx.members.games(uid) {
+                    self.send_game_state(ctx, uid, game_id).await;
+                }
+            }
+            WsDisconnect { respond_to, uid } => {
+                self.handle_disconnect(ctx, respond_to, uid).await;
+            }
+            FlagCheck { game_id } => {
+                self.handle_flag_check(ctx, game_id).await;
+            }
+            GraceExpired { uid, since } => {
+                self.handle_grace_expired(ctx, uid, since).await;
+            }
+            Matchmake => {
+                ctx.matchmake_scheduled = false;
+                self.matchmake(ctx, Instant::now()).await;
+            }
+            RatingsLoaded { id, ratings } => {
+                self.handle_ratings_loaded(ctx, id, ratings).await;
+            }
+            GetRatings { respond_to, uid } => {
+                self.send_ratings(respond_to, uid);
+            }
+            GetEngines { respond_to } => {
+                let engines = self
+                    .config
+                    .engines
+                    .iter()
+                    .map(|(name, config)| EngineInfo {
+                        name: name.clone(),
+                        levels: config.levels.len(),
+                    })
+                    .collect();
+                let _ = respond_to.send(WsMessage::Engines(engines)).await;
+            }
+            Resign { game_id, uid } => {
+                self.handle_resign(ctx, game_id, uid).await;
+            }
+            OfferDraw { game_id, uid } => {
+                self.handle_offer_draw(ctx, game_id, uid).await;
+            }
+            AcceptDraw { game_id, uid } => {
+                self.handle_accept_draw(ctx, game_id, uid).await;
+            }
+            DeclineDraw { game_id, uid } => {
+                self.handle_decline_draw(ctx, game_id, uid).await;
+            }
+            Abort { game_id, uid } => {
+                self.handle_abort(ctx, game_id, uid).await;
+            }
+            TakebackRequest { game_id, uid } => {
+                self.handle_takeback_request(ctx, game_id, uid).await;
+            }
+            TakebackAccept { game_id, uid } => {
+                self.handle_takeback_accept(ctx, game_id, uid).await;
+            }
+            TakebackDecline { game_id, uid } => {
+                self.handle_takeback_decline(ctx, game_id, uid).await;
+            }
+            RematchOffer { game_id, uid } => {
+                self.handle_rematch_offer(ctx, game_id, uid).await;
+            }
+            RematchAccept { game_id, uid } => {
+                self.handle_rematch_accept(ctx, game_id, uid).await;
+            }
+            Lobby { respond_to, uid } => {
+                self.handle_lobby(ctx, respond_to, uid).await;
+            }
+            LeaveLobby { respond_to } => {
+                ctx.lobby
+                    .retain(|subscriber| !subscriber.same_channel(&respond_to));
+            }
+            AcceptSeek {
+                id,
+                respond_to,
+                uid,
+                name,
+            } => {
+                self.handle_accept_seek(ctx, id, respond_to, uid, name)
+                    .await;
+            }
+            Challenge {
+                to_user,
+                msg,
+                respond_to,
+                uid,
+                name,
+            } => {
+                self.handle_challenge(ctx, to_user, msg, respond_to, uid, name)
+                    .await;
+            }
+            ChallengeAccept {
+                id,
+                respond_to,
+                uid,
+                name,
+            } => {
+                self.handle_challenge_accept(ctx, id, respond_to, uid, name)
+                    .await;
+            }
+            ChallengeDecline { id, uid } => {
+                self.handle_challenge_decline(ctx, id, uid).await;
+            }
+            ChallengeCancel { id, uid } => {
+                self.handle_challenge_cancel(ctx, id, uid).await;
+            }
+            ChallengeExpired { id } => {
+                if let Some(challenge) = ctx.challenges.remove(&id) {
+                    let msg = WsMessage::ChallengeExpired { id };
+                    send_challengers(ctx, &challenge, msg).await;
+                }
+            }
+            ChallengeChecked { id, exists } => {
+                self.handle_challenge_checked(ctx, id, exists).await;
+            }
+            Spectate {
+                game_id,
+                respond_to,
+                uid,
+            } => {
+                self.handle_spectate(ctx, game_id, respond_to, uid).await;
+            }
+            Chat {
+                room,
+                text,
+                respond_to,
+                uid,
+                name,
+            } => {
+                self.handle_chat(ctx, room, text, respond_to, uid, name)
+                    .await;
+            }
+            Mute { uid, muted } => {
+                let mutes = ctx.mutes.entry(uid).or_default();
+                if mutes.len() < self.config.mute_limit {
+                    mutes.insert(muted);
+                }
+            }
+            Unmute { uid, muted } => {
+                if let Some(mutes) = ctx.mutes.get_mut(&uid) {
+                    mutes.remove(&muted);
+                    if mutes.is_empty() {
+                        ctx.mutes.remove(&uid);
+                    }
+                }
+            }
+            RestoreCorrespondence { games } => {
+                self.restore_correspondence(ctx, games);
+            }
+            ChatWindowExpired { uid } => {
+                // forget users quiet for a whole window
+                let window = self.config.chat_window;
+                let now = Instant::now();
+                if let Some(sent) = ctx.chat_sent.get(&uid) {
+                    if sent.back().is_none_or(|at| *at + window <= now) {
+                        ctx.chat_sent.remove(&uid);
+                    }
+                }
+            }
+            #[cfg(test)]
+            CheckMembers { respond_to } => {
+                let _ = respond_to.send(check_members(ctx));
+            }
+            Unspectate {
+                game_id,
+                respond_to,
             } => {
-                self.handle_game_preference(ctx, msg, respond_to, uid).await;
+                self.handle_unspectate(ctx, game_id, &respond_to).await;
+            }
+            RematchExpired { game_id, since } => {
+                match ctx.rematches.get(&game_id) {
+                    Some(rematch) if rematch.since == since => {
+                        self.cancel_rematch(ctx, game_id).await;
+                    }
+                    _ => (), // taken, cancelled or offered later
+                }
             }
-            Move { uci, uid } => {
-                self.handle_move(ctx, &uci, uid).await;
+        }
+    }
+
+    // deliver msg to the Hub itself at the given instant
+    fn schedule(&self, at: Instant, msg: Message) {
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(at).await;
+            if let Some(sender) = sender.upgrade() {
+                let _ = sender.send(msg).await;
             }
-            WsDisconnect { uid: _uid } => {
-                todo!();
+        });
+    }
+
+    fn schedule_flag_check(&self, live_game: &LiveGame) {
+        let at = match (&live_game.clock, live_game.deadline) {
+            (Some(clock), _) => clock.deadline(live_game.game.turn()),
+            (None, Some(deadline)) => {
+                let left = (deadline - Utc::now()).to_std().unwrap_or_default();
+                Instant::now() + left
             }
+            (None, None) => return,
+        };
+        let game_id = live_game.id;
+        self.schedule(at, Message::FlagCheck { game_id });
+    }
+
+    // Rewrite a correspondence game in the Db, so that it survives a restart
+    fn store_correspondence(&self, ctx: &HubState, game_id: LiveGameId) {
+        let live_game = match ctx.games.get(&game_id) {
+            Some(game) if game.white >= 0 && game.black >= 0 => game,
+            _ => return, // engines can't be restored
+        };
+        let name = |uid| ctx.players.get(&uid).map(|player| player.name.clone());
+        let names = [name(live_game.white), name(live_game.black)];
+        let stored = match live_game.stored(names.map(Option::unwrap_or_default)) {
+            Some(stored) => stored,
+            None => return, // not a correspondence game
+        };
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(Write::SaveCorrespondence(stored));
+        }
+    }
+
+    async fn send_clock(&self, ctx: &mut HubState, game_id: LiveGameId, now: Instant) {
+        let live_game = &ctx.games[&game_id];
+        let clock = match &live_game.clock {
+            Some(clock) => clock,
+            None => return,
+        };
+        let msg = WsMessage::Clock {
+            game_id,
+            clock: clock.snapshot(live_game.game.turn(), now),
+        };
+        for uid in [live_game.white, live_game.black] {
+            send_player(ctx, uid, msg.clone()).await;
         }
+        let live_game = ctx.games.get_mut(&game_id).expect("live game");
+        live_game.send_spectators(msg);
     }
 
     async fn handle_game_preference(
@@ -86,152 +910,3508 @@ impl Hub {
         msg: GamePreference,
         respond_to: mpsc::Sender<WsMessage>,
         uid: IdType,
+        name: String,
     ) {
-        let reqs = &mut ctx.requests;
-        if reqs.is_empty() {
-            println!("HUB request from {}: noone there", uid);
-            reqs.push_back(GameRequest {
-                msg,
-                respond_to,
-                uid,
-            });
-
+        if let Err(reason) = msg.tc.validate() {
+            let _ = respond_to
+                .send(WsMessage::GameRequestRejected { reason })
+                .await;
             return;
         }
-        let opponent = reqs.remove(0).expect("non empty game requests");
-        let my_player = Player {
-            uid,
-            respond_to: respond_to.clone(),
-            color: WsColor::White,
-            opponent: opponent.uid,
+        while let Some(game_id) = rematch_of(ctx, uid) {
+            self.cancel_rematch(ctx, game_id).await;
+        }
+        if let OpponentPreference::Engine(engine) = &msg.opponent {
+            let engine = engine.clone();
+            self.start_engine_game(ctx, msg, engine, respond_to, uid, name)
+                .await;
+            return;
+        }
+        let request = GameRequest {
+            id: 0, // on seeking
+            msg,
+            respond_to,
+            uid,
+            name,
+            rating: None,
+            since: Instant::now(),
+        };
+        self.load_ratings(ctx, Rated::Seek(request)).await;
+    }
+
+    // The request is rated, seek an opponent
+    async fn seek(&mut self, ctx: &mut HubState, mut request: GameRequest) {
+        let now = Instant::now();
+        ctx.last_seek_id += 1;
+        request.id = ctx.last_seek_id;
+        request.since = now;
+        send_lobby(ctx, WsMessage::SeekCreated(request.seek()));
+        ctx.requests.push_back(request);
+        self.matchmake(ctx, now).await;
+    }
+
+    // Ratings of rated requests come from the Db off the Hub loop, the
+    // requests wait in the Hub until they are loaded
+    async fn load_ratings(&mut self, ctx: &mut HubState, rated: Rated) {
+        let loads: Vec<_> = rated
+            .requests()
+            .iter()
+            .map(|request| (request.uid, request.msg.tc.category()))
+            .collect();
+        if loads.iter().all(|(_, category)| category.is_none()) {
+            let ratings = vec![None; loads.len()];
+            self.rated(ctx, rated, ratings).await;
+            return;
+        }
+        ctx.last_rating_load += 1;
+        let id = ctx.last_rating_load;
+        ctx.rating_loads.insert(id, rated);
+        let (db, sender) = (self.db.clone(), self.sender.clone());
+        tokio::spawn(async move {
+            let mut ratings = vec![];
+            for (uid, category) in loads {
+                let rating = match category {
+                    Some(category) => Some(current_rating(db.as_ref(), uid, category).await),
+                    None => None,
+                };
+                ratings.push(rating);
+            }
+            if let Some(sender) = sender.upgrade() {
+                let _ = sender.send(Message::RatingsLoaded { id, ratings }).await;
+            }
+        });
+    }
+
+    async fn handle_ratings_loaded(
+        &mut self,
+        ctx: &mut HubState,
+        id: RatingLoadId,
+        ratings: Vec<Option<Rating>>,
+    ) {
+        if let Some(rated) = ctx.rating_loads.remove(&id) {
+            self.rated(ctx, rated, ratings).await;
+        }
+    }
+
+    async fn rated(&mut self, ctx: &mut HubState, rated: Rated, ratings: Vec<Option<Rating>>) {
+        match rated.with_ratings(ratings) {
+            // gone while its rating loaded
+            Rated::Seek(request) if request.respond_to.is_closed() => (),
+            Rated::Seek(request) => self.seek(ctx, request).await,
+            Rated::Pairing(older, newer) => {
+                for (request, other) in [(&older, &newer), (&newer, &older)] {
+                    if request.respond_to.is_closed() {
+                        let reason = String::from("opponent left");
+                        let resp = WsMessage::GameRequestRejected { reason };
+                        let _ = other.respond_to.send(resp).await;
+                        return;
+                    }
+                }
+                self.start_game(ctx, older, newer, Instant::now()).await;
+            }
+        }
+    }
+
+    // An engine process of its own plays the opponent, unrated
+    async fn start_engine_game(
+        &mut self,
+        ctx: &mut HubState,
+        msg: GamePreference,
+        engine_name: String,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+        name: String,
+    ) {
+        ctx.last_engine_uid -= 1;
+        let engine_uid = ctx.last_engine_uid;
+        let engine_tx = match self.config.engines.get(&engine_name) {
+            Some(config) => config.level(msg.level).and_then(|level| {
+                engine::spawn(config, level, msg.tc, engine_uid, self.engines.downgrade()).map_err(
+                    |e| {
+                        eprintln!("HUB engine {} for {} error {}", engine_name, uid, e);
+                        format!("{} is not available", engine_name)
+                    },
+                )
+            }),
+            None => Err(format!("{} is not configured", engine_name)),
         };
-        let opponent_player = Player {
-            uid: opponent.uid,
-            color: WsColor::Black,
-            respond_to: opponent.respond_to.clone(),
-            opponent: uid,
+        let engine_tx = match engine_tx {
+            Ok(engine_tx) => engine_tx,
+            Err(reason) => {
+                let _ = respond_to
+                    .send(WsMessage::GameRequestRejected { reason })
+                    .await;
+                return;
+            }
         };
-        let live_game = LiveGame {
-            game: Chess::default(),
-            tc: msg.tc,
-            white: uid,
-            black: opponent.uid,
+        let engine = GameRequest {
+            id: 0,
+            msg: GamePreference {
+                tc: msg.tc,
+                disable_takebacks: true, // engines don't answer takeback requests
+                ..Default::default()
+            },
+            respond_to: engine_tx,
+            uid: engine_uid,
+            name: engine_name,
+            rating: None,
+            since: Instant::now(),
+        };
+        let human = GameRequest {
+            id: 0,
+            msg,
+            respond_to,
+            uid,
+            name,
+            rating: None,
+            since: engine.since,
+        };
+        self.start_game(ctx, human, engine, Instant::now()).await;
+    }
+
+    // Oldest request first, paired with the closest rated compatible one
+    fn find_pair(&self, requests: &GameRequests, now: Instant) -> Option<(usize, usize)> {
+        for (i, req) in requests.iter().enumerate() {
+            let best = requests
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .filter(|(_, other)| req.pairs_with(other, &self.config, now))
+                .min_by(|(_, a), (_, b)| {
+                    let gap_a = rating_gap(req, a).unwrap_or_default();
+                    let gap_b = rating_gap(req, b).unwrap_or_default();
+                    gap_a.total_cmp(&gap_b)
+                });
+            if let Some((j, _)) = best {
+                return Some((i, j));
+            }
+        }
+        None
+    }
+
+    async fn matchmake(&mut self, ctx: &mut HubState, now: Instant) {
+        while let Some((i, j)) = self.find_pair(&ctx.requests, now) {
+            let newer = ctx.requests.remove(j).expect("found game request");
+            let older = ctx.requests.remove(i).expect("found game request");
+            for id in [older.id, newer.id] {
+                send_lobby(ctx, WsMessage::SeekRemoved { id });
+            }
+            self.start_game(ctx, older, newer, now).await;
+        }
+
+        // rating windows widen, retry later
+        let rated_waiting = ctx.requests.iter().any(|req| req.rating.is_some());
+        if rated_waiting && !ctx.matchmake_scheduled {
+            ctx.matchmake_scheduled = true;
+            self.schedule(now + self.config.matchmaking_tick, Message::Matchmake);
+        }
+    }
+
+    async fn start_game(
+        &mut self,
+        ctx: &mut HubState,
+        older: GameRequest,
+        newer: GameRequest,
+        now: Instant,
+    ) {
+        let older_color: WsColor = older
+            .msg
+            .color
+            .against(newer.msg.color)
+            .expect("compatible color")
+            .into();
+        // the waiting seek sets the time control
+        let tc = older.msg.tc;
+        let older_player = Player::new(older.uid, older.name, older.respond_to);
+        let newer_player = Player::new(newer.uid, newer.name, newer.respond_to);
+        let (white, black) = match older_color {
+            WsColor::White => (older_player, newer_player),
+            WsColor::Black => (newer_player, older_player),
         };
 
-        let game_id = (uid, opponent.uid);
+        let mut live_game = LiveGame::new(tc, white.uid, black.uid, now);
+        live_game.rated = older.rating.is_some() && newer.rating.is_some();
+        live_game.takebacks = !older.msg.disable_takebacks && !newer.msg.disable_takebacks;
+        self.create_game(ctx, [white, black], live_game, now).await;
+    }
+
+    async fn create_game(
+        &mut self,
+        ctx: &mut HubState,
+        [white, black]: [Player; 2],
+        live_game: LiveGame,
+        now: Instant,
+    ) {
+        let game_id = live_game.id;
+
+        ctx.members.join(game_id, [white.uid, black.uid]);
+        for (player, color) in [(white, WsColor::White), (black, WsColor::Black)] {
+            let resp = WsMessage::GameResponse { game_id, color };
+            println!("HUB request resp to {} {:?}", player.uid, resp);
+            let _ = player.respond_to.send(resp).await;
+            match ctx.players.get_mut(&player.uid) {
+                // already playing, the newest connection plays
+                Some(playing) => playing.respond_to = player.respond_to,
+                None => {
+                    ctx.players.insert(player.uid, player);
+                }
+            }
+        }
 
-        ctx.players.insert(uid, my_player);
-        ctx.players.insert(uid, opponent_player);
+        self.schedule_flag_check(&live_game);
         ctx.games.insert(game_id, live_game);
+        self.send_clock(ctx, game_id, now).await;
+        self.store_correspondence(ctx, game_id);
+        if let Some(game) = lobby_game(ctx, game_id) {
+            send_lobby(ctx, WsMessage::GameStarted(game));
+        }
+    }
+
+    async fn handle_lobby(
+        &mut self,
+        ctx: &mut HubState,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+    ) {
+        let seeks = ctx.requests.iter().map(GameRequest::seek).collect();
+        let games = ctx
+            .games
+            .keys()
+            .filter_map(|game_id| lobby_game(ctx, *game_id))
+            .collect();
+        let _ = respond_to
+            .send(WsMessage::LobbyState { seeks, games })
+            .await;
+        if !ctx.lobby.iter().any(|s| s.same_channel(&respond_to)) {
+            ctx.lobby.push(Subscriber { uid, respond_to });
+        }
+    }
 
-        let resp = WsMessage::GameResponse(WsColor::White);
-        println!("HUB request {} resp to white {:?}", uid, resp);
-        let _ = respond_to.send(resp).await;
+    // Pick an open seek instead of waiting to be paired
+    async fn handle_accept_seek(
+        &mut self,
+        ctx: &mut HubState,
+        id: SeekId,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+        name: String,
+    ) {
+        let index = ctx
+            .requests
+            .iter()
+            .position(|req| req.id == id && req.uid != uid);
+        let seek = match index.and_then(|i| ctx.requests.remove(i)) {
+            Some(seek) => seek,
+            None => {
+                let reason = String::from("no such seek");
+                let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+                return;
+            }
+        };
+        send_lobby(ctx, WsMessage::SeekRemoved { id });
+        while let Some(game_id) = rematch_of(ctx, uid) {
+            self.cancel_rematch(ctx, game_id).await;
+        }
 
-        let resp = WsMessage::GameResponse(WsColor::Black);
-        println!("HUB request {} resp to black {:?}", opponent.uid, resp);
-        let _ = opponent.respond_to.send(resp).await;
+        // rated like an automatic pairing, whatever the rating gap
+        let now = Instant::now();
+        let accepting = GameRequest {
+            id: 0,
+            msg: GamePreference {
+                color: ColorPreference::Any,
+                ..seek.msg.clone()
+            },
+            respond_to,
+            uid,
+            name,
+            rating: None,
+            since: now,
+        };
+        self.load_ratings(ctx, Rated::Pairing(seek, accepting))
+            .await;
     }
 
-    async fn handle_move(&mut self, ctx: &mut HubState, uci: &str, uid: IdType) {
-        let my_player = match ctx.players.get(&uid) {
-            Some(player) => player,
+    async fn handle_move(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        uci: &str,
+        uid: IdType,
+    ) {
+        let live_game = match ctx.games.get_mut(&game_id) {
+            Some(game) => game,
             None => {
-                println!("HUB move uci {}, no my player for uid {}", uci, uid);
+                println!("HUB move uci {}, no live game game id {}", uci, game_id);
                 return;
             }
         };
-        let opponent_player = match ctx.players.get(&my_player.opponent) {
-            Some(player) => player,
+        let (color, opponent) = match live_game.seat(uid) {
+            Some(seat) => seat,
             None => {
-                println!(
-                    "HUB move uci {}, no opponent player for uid {}",
-                    uci, my_player.opponent
-                );
+                println!("HUB move uci {}, uid {} doesn't play {}", uci, uid, game_id);
                 return;
             }
         };
-        let game_id = match my_player.color {
-            WsColor::White => (my_player.uid, opponent_player.uid),
-            WsColor::Black => (opponent_player.uid, my_player.uid),
+        let (my_player, opponent_player) = match (ctx.players.get(&uid), ctx.players.get(&opponent))
+        {
+            (Some(my_player), Some(opponent_player)) => (my_player, opponent_player),
+            _ => {
+                println!("HUB move uci {}, no players for game id {}", uci, game_id);
+                return;
+            }
         };
-        let live_game = match ctx.games.get_mut(&game_id) {
-            Some(game) => game,
+        let now = Instant::now();
+        let turn = live_game.game.turn();
+        if turn != color {
+            println!("HUB move uci {}, not the turn of uid {}", uci, uid);
+            let resp = WsMessage::MoveRejected {
+                game_id,
+                uci: uci.to_owned(),
+                reason: String::from("not your turn"),
+            };
+            let _ = my_player.respond_to.send(resp).await;
+            return;
+        }
+        if live_game.out_of_time(now) {
+            println!("HUB move uci {}, uid {} out of time", uci, uid);
+            self.handle_flag_check(ctx, game_id).await;
+            return;
+        }
+        match live_game.game.make_move(uci) {
+            Ok(m) => {
+                println!("HUB move uci {}, success", uci);
+                let clock = live_game.clock.as_mut().map(|clock| {
+                    clock
+                        .punch(turn, now)
+                        .expect("flag checked before the move");
+                    clock.remaining(turn, !turn, now)
+                });
+                live_game.moves.push(PlayedMove { m, clock });
+                live_game.record_position();
+                live_game.renew_deadline();
+                let resp = WsMessage::Move {
+                    game_id,
+                    uci: uci.to_owned(),
+                };
+                let _ = opponent_player.respond_to.send(resp.clone()).await;
+                live_game.send_spectators(resp);
+
+                // a move settles a pending takeback request
+                live_game.takeback = None;
+
+                // moving withdraws my draw offer
+                if live_game.draw_offer == Some(turn) {
+                    live_game.draw_offer = None;
+                    let resp = WsMessage::DrawOfferWithdrawn { game_id };
+                    let _ = opponent_player.respond_to.send(resp).await;
+                }
+
+                self.send_clock(ctx, game_id, now).await;
+                let live_game = &ctx.games[&game_id];
+                match live_game.termination() {
+                    Some((result, reason)) => self.end_game(ctx, game_id, result, reason).await,
+                    None => {
+                        self.schedule_flag_check(live_game);
+                        self.store_correspondence(ctx, game_id);
+                    }
+                }
+            }
+            Err(e) => {
+                println!("HUB move uci {}, make move error {:?}", uci, e);
+                let resp = WsMessage::MoveRejected {
+                    game_id,
+                    uci: uci.to_owned(),
+                    reason: e.to_string(),
+                };
+                let _ = my_player.respond_to.send(resp).await;
+            }
+        }
+    }
+
+    async fn handle_resign(&mut self, ctx: &mut HubState, game_id: LiveGameId, uid: IdType) {
+        let (color, _) = match live_player(ctx, uid, game_id) {
+            Some(live_player) => live_player,
+            None => return, // not in a game
+        };
+        println!("HUB uid {} resigns game {:?}", uid, game_id);
+        let result = GameResult::win(!color);
+        self.end_game(ctx, game_id, result, GameEndReason::Resignation)
+            .await;
+    }
+
+    // Aborting is allowed until both sides have moved
+    async fn handle_abort(&mut self, ctx: &mut HubState, game_id: LiveGameId, uid: IdType) {
+        if live_player(ctx, uid, game_id).is_none() {
+            return; // not in the game
+        }
+        if ctx.games[&game_id].moves.len() >= 2 {
+            return reject(ctx, uid, "both sides have moved, resign instead").await;
+        }
+        println!("HUB uid {} aborts game {:?}", uid, game_id);
+        self.end_game(ctx, game_id, GameResult::Aborted, GameEndReason::Aborted)
+            .await;
+    }
+
+    async fn handle_offer_draw(&mut self, ctx: &mut HubState, game_id: LiveGameId, uid: IdType) {
+        let (color, opponent) = match live_player(ctx, uid, game_id) {
+            Some(live_player) => live_player,
+            None => return, // not in a game
+        };
+        let live_game = ctx.games.get_mut(&game_id).expect("live player game");
+        match live_game.draw_offer {
+            // crossing offers are an agreement
+            Some(offerer) if offerer != color => {
+                self.end_game(ctx, game_id, GameResult::Draw, GameEndReason::DrawAgreement)
+                    .await;
+            }
+            Some(_) => (), // already offered
             None => {
-                println!("HUB move uci {}, no live game game id {:?}", uci, game_id);
-                return;
+                live_game.draw_offer = Some(color);
+                send_player(ctx, opponent, WsMessage::OfferDraw { game_id }).await;
             }
+        }
+    }
+
+    async fn handle_accept_draw(&mut self, ctx: &mut HubState, game_id: LiveGameId, uid: IdType) {
+        let (color, _) = match live_player(ctx, uid, game_id) {
+            Some(live_player) => live_player,
+            None => return, // not in a game
         };
-        let game = &mut live_game.game;
-        match game.make_move(uci) {
-            Ok(_) => println!("HUB move uci {}, success", uci),
-            Err(e) => println!("HUB move uci {}, make move error {:?}", uci, e),
+        if ctx.games[&game_id].draw_offer != Some(!color) {
+            return reject(ctx, uid, "no draw offer").await;
         }
+        self.end_game(ctx, game_id, GameResult::Draw, GameEndReason::DrawAgreement)
+            .await;
     }
 
-    async fn run(mut self) -> io::Result<()> {
-        let mut ctx = HubState {
-            requests: GameRequests::default(),
-            players: Players::default(),
-            games: LiveGames::default(),
+    async fn handle_decline_draw(&mut self, ctx: &mut HubState, game_id: LiveGameId, uid: IdType) {
+        let (color, opponent) = match live_player(ctx, uid, game_id) {
+            Some(live_player) => live_player,
+            None => return, // not in a game
         };
-        while let Some(msg) = self.receiver.recv().await {
-            self.handle_message(&mut ctx, msg).await;
+        let live_game = ctx.games.get_mut(&game_id).expect("live player game");
+        if live_game.draw_offer != Some(!color) {
+            return reject(ctx, uid, "no draw offer").await;
         }
-        Ok(())
+        live_game.draw_offer = None;
+        send_player(ctx, opponent, WsMessage::DeclineDraw { game_id }).await;
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct Handle {
-    pub sender: mpsc::Sender<Message>,
-}
+    async fn handle_takeback_request(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        uid: IdType,
+    ) {
+        let (color, opponent) = match live_player(ctx, uid, game_id) {
+            Some(live_player) => live_player,
+            None => return, // not in a game
+        };
+        let live_game = ctx.games.get_mut(&game_id).expect("live player game");
+        if !live_game.takebacks {
+            return reject(ctx, uid, "takebacks are disabled").await;
+        }
+        if live_game.takeback_plies(color).is_none() {
+            return reject(ctx, uid, "no move to take back").await;
+        }
+        live_game.takeback = Some(color);
+        send_player(ctx, opponent, WsMessage::TakebackRequest { game_id }).await;
+    }
 
-impl Handle {
-    pub fn new() -> Self {
-        let (sender, receiver) = mpsc::channel(256);
-        tokio::spawn(Hub { receiver }.run());
-        Handle { sender }
+    async fn handle_takeback_accept(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        uid: IdType,
+    ) {
+        let (color, opponent) = match live_player(ctx, uid, game_id) {
+            Some(live_player) => live_player,
+            None => return, // not in a game
+        };
+        let live_game = ctx.games.get_mut(&game_id).expect("live player game");
+        if live_game.takeback != Some(!color) {
+            return reject(ctx, uid, "no takeback request").await;
+        }
+        let plies = live_game
+            .takeback_plies(!color)
+            .expect("checked on request, no move since");
+        println!("HUB game {:?} takes back {} plies", game_id, plies);
+        live_game.take_back(plies, Instant::now());
+
+        self.schedule_flag_check(&ctx.games[&game_id]);
+        self.store_correspondence(ctx, game_id);
+        for uid in [uid, opponent] {
+            self.send_game_state(ctx, uid, game_id).await;
+        }
+        if let Some(state) = spectator_state(ctx, game_id) {
+            let live_game = ctx.games.get_mut(&game_id).expect("live game");
+            live_game.send_spectators(WsMessage::SpectatorState(state));
+        }
     }
 
-    pub async fn send(&self, msg: Message) -> Result<(), mpsc::error::SendError<Message>> {
-        self.sender.send(msg).await
+    async fn handle_takeback_decline(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        uid: IdType,
+    ) {
+        let (color, opponent) = match live_player(ctx, uid, game_id) {
+            Some(live_player) => live_player,
+            None => return, // not in a game
+        };
+        let live_game = ctx.games.get_mut(&game_id).expect("live player game");
+        if live_game.takeback != Some(!color) {
+            return reject(ctx, uid, "no takeback request").await;
+        }
+        live_game.takeback = None;
+        send_player(ctx, opponent, WsMessage::TakebackDecline { game_id }).await;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    async fn handle_spectate(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+    ) {
+        let state = match spectator_state(ctx, game_id) {
+            Some(state) => state,
+            None => {
+                let reason = String::from("no such live game");
+                let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+                return;
+            }
+        };
+        let _ = respond_to.send(WsMessage::SpectatorState(state)).await;
+        let live_game = ctx.games.get_mut(&game_id).expect("spectated game");
+        if !live_game
+            .spectators
+            .iter()
+            .any(|s| s.same_channel(&respond_to))
+        {
+            live_game.spectators.push(Subscriber { uid, respond_to });
+            send_spectator_count(ctx, game_id);
+        }
+    }
 
-    // Test only
-    async fn send_game_request(handle: Handle, msg: GamePreference, uid: IdType) {
-        let (respond_to, mut receiver) = mpsc::channel::<WsMessage>(8);
-        let msg = Message::GameRequest {
-            msg,
-            respond_to,
+    async fn handle_unspectate(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        respond_to: &mpsc::Sender<WsMessage>,
+    ) {
+        let live_game = match ctx.games.get_mut(&game_id) {
+            Some(game) => game,
+            None => return, // game is already over
+        };
+        let count = live_game.spectators.len();
+        live_game
+            .spectators
+            .retain(|spectator| !spectator.same_channel(respond_to));
+        if live_game.spectators.len() != count {
+            send_spectator_count(ctx, game_id);
+        }
+    }
+
+    // A chat line to the players' room of the sender's game, to the spectators'
+    // room of a watched game or to the lobby, never back to the sender
+    async fn handle_chat(
+        &mut self,
+        ctx: &mut HubState,
+        room: ChatRoom,
+        text: String,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+        name: String,
+    ) {
+        let len = text.chars().count();
+        let rejected = if len == 0 || len > self.config.chat_max_len {
+            Some("chat line is empty or too long")
+        } else if !chat_member(ctx, &room, uid) {
+            Some("not in this chat room")
+        } else if !self.chat_allowed(ctx, uid, Instant::now()) {
+            Some("too many chat lines, slow down")
+        } else {
+            None
+        };
+        if let Some(reason) = rejected {
+            let reason = String::from(reason);
+            let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+            return;
+        }
+
+        let line = ChatLine {
+            room,
             uid,
+            name,
+            text,
         };
+        let muted_by = |recipient: IdType| {
+            ctx.mutes
+                .get(&recipient)
+                .is_some_and(|mutes| mutes.contains(&uid))
+        };
+        // chat never waits on a busy recipient, lagging subscribers are dropped
+        let msg = WsMessage::ChatLine(line.clone());
+        let to = |s: &Subscriber| s.uid != uid && !muted_by(s.uid);
+        match room {
+            ChatRoom::Game(game_id) => {
+                let (_, opponent) = live_player(ctx, uid, game_id).expect("chat member");
+                match ctx.players.get(&opponent) {
+                    Some(player) if !muted_by(opponent) => {
+                        let _ = player.respond_to.try_send(msg);
+                    }
+                    _ => (),
+                }
+                let live_game = ctx.games.get_mut(&game_id).expect("live game");
+                live_game.chat.push(line);
+            }
+            ChatRoom::Spectators(game_id) => {
+                let live_game = ctx.games.get_mut(&game_id).expect("live game");
+                send_subscribers(&mut live_game.spectators, &msg, to);
+                live_game.chat.push(line);
+            }
+            ChatRoom::Lobby => send_subscribers(&mut ctx.lobby, &msg, to),
+        }
+    }
 
-        let _ = handle.send(msg).await;
-        let msg = receiver.recv().await.expect("Hub is dead");
-        println!("reply {:?}", msg);
+    // Counts the line if the user is within its chat rate
+    fn chat_allowed(&self, ctx: &mut HubState, uid: IdType, now: Instant) -> bool {
+        let sent = ctx.chat_sent.entry(uid).or_default();
+        while matches!(sent.front(), Some(at) if *at + self.config.chat_window <= now) {
+            sent.pop_front();
+        }
+        if sent.len() >= self.config.chat_limit {
+            return false;
+        }
+        sent.push_back(now);
+        let at = now + self.config.chat_window;
+        self.schedule(at, Message::ChatWindowExpired { uid });
+        true
     }
 
-    #[tokio::test]
-    async fn chess_hub() -> Result<(), Box<dyn std::error::Error>> {
-        let handle = Handle::new();
-        let mut jhs = vec![];
-        for i in 0..8 {
-            let handle = handle.clone();
-            let jh = tokio::spawn(async move {
-                send_game_request(handle, GamePreference::default(), i).await;
-            });
-            jhs.push(jh);
+    async fn handle_connect(
+        &mut self,
+        ctx: &mut HubState,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+    ) {
+        // pending challenges reach the user as soon as it connects
+        let pending = ctx.challenges.values().filter(|c| c.to == uid);
+        for challenge in pending {
+            let msg = WsMessage::ChallengeOffer(challenge.info());
+            let _ = respond_to.send(msg).await;
         }
-        for jh in jhs {
-            let _ = jh.await;
+        ctx.connections.insert(uid, respond_to.clone());
+
+        // a rematch starts on the newest connection
+        let rematch_players = ctx
+            .rematches
+            .values_mut()
+            .flat_map(|r| r.players.iter_mut());
+        for player in rematch_players.filter(|player| player.uid == uid) {
+            player.respond_to = respond_to.clone();
         }
 
-        Ok(())
+        let player = match ctx.players.get_mut(&uid) {
+            Some(player) => player,
+            None => return, // not in a game
+        };
+        println!("HUB uid {} connected to its live games", uid);
+
+        // the newest connection plays
+        player.respond_to = respond_to;
+        let games = ctx.members.games(uid);
+        let back = player.away_since.take().is_some();
+        for game_id in games {
+            if back {
+                let (_, opponent) = live_player(ctx, uid, game_id).expect("player game");
+                let msg = WsMessage::OpponentReconnected { game_id };
+                send_player(ctx, opponent, msg).await;
+            }
+            self.send_game_state(ctx, uid, game_id).await;
+        }
+    }
+
+    async fn send_game_state(&self, ctx: &HubState, uid: IdType, game_id: LiveGameId) {
+        let player = match ctx.players.get(&uid) {
+            Some(player) => player,
+            None => return, // not in a game
+        };
+        let live_game = match ctx.games.get(&game_id) {
+            Some(game) => game,
+            None => return,
+        };
+        let (color, opponent) = match live_game.seat(uid) {
+            Some(seat) => seat,
+            None => return,
+        };
+        let opponent_name = match ctx.players.get(&opponent) {
+            Some(opponent) => opponent.name.clone(),
+            None => String::new(),
+        };
+        let now = Instant::now();
+        let turn = live_game.game.turn();
+        let state = GameState {
+            game_id,
+            fen: start_fen(),
+            moves: live_game.uci_moves(),
+            color: color.into(),
+            opponent,
+            opponent_name,
+            draw_offer: live_game.draw_offer.map(WsColor::from),
+            takeback: live_game.takeback.map(WsColor::from),
+            clock: live_game
+                .clock
+                .as_ref()
+                .map(|clock| clock.snapshot(turn, now)),
+            deadline: live_game.deadline.map(|deadline| deadline.timestamp()),
+        };
+        let _ = player.respond_to.send(WsMessage::GameState(state)).await;
+    }
+
+    async fn handle_disconnect(
+        &mut self,
+        ctx: &mut HubState,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+    ) {
+        // requests of a closed connection can't be answered
+        let (closed, open) = ctx
+            .requests
+            .drain(..)
+            .partition(|req| req.respond_to.same_channel(&respond_to));
+        ctx.requests = open;
+        for req in closed.iter() {
+            send_lobby(ctx, WsMessage::SeekRemoved { id: req.id });
+        }
+        ctx.lobby
+            .retain(|subscriber| !subscriber.same_channel(&respond_to));
+        if matches!(ctx.connections.get(&uid), Some(tx) if tx.same_channel(&respond_to)) {
+            ctx.connections.remove(&uid);
+            ctx.mutes.remove(&uid); // mutes last while connected
+        }
+
+        // nor does it watch games anymore
+        let watched: Vec<_> = ctx
+            .games
+            .iter()
+            .filter(|(_, game)| game.spectators.iter().any(|s| s.same_channel(&respond_to)))
+            .map(|(game_id, _)| *game_id)
+            .collect();
+        for game_id in watched {
+            self.handle_unspectate(ctx, game_id, &respond_to).await;
+        }
+
+        let player = match ctx.players.get_mut(&uid) {
+            Some(player) if player.respond_to.same_channel(&respond_to) => player,
+            _ => return, // not in a game or an old connection closed
+        };
+        println!("HUB uid {} disconnected from its live games", uid);
+
+        let since = Instant::now();
+        player.away_since = Some(since);
+        for game_id in ctx.members.games(uid) {
+            let (_, opponent) = live_player(ctx, uid, game_id).expect("player game");
+            let msg = WsMessage::OpponentDisconnected { game_id };
+            send_player(ctx, opponent, msg).await;
+        }
+        let at = since + self.config.reconnect_grace;
+        self.schedule(at, Message::GraceExpired { uid, since });
+    }
+
+    async fn handle_grace_expired(&mut self, ctx: &mut HubState, uid: IdType, since: Instant) {
+        let away = ctx.players.get(&uid).and_then(|player| player.away_since);
+        if away != Some(since) {
+            return; // reconnected in time or the games are over
+        }
+        for game_id in ctx.members.games(uid) {
+            let live_game = &ctx.games[&game_id];
+            if live_game.tc.days().is_some() {
+                continue; // correspondence players come and go
+            }
+            let (color, _) = live_game.seat(uid).expect("player game");
+            println!("HUB uid {} didn't come back to game {}", uid, game_id);
+
+            // abort unless both sides have moved
+            let (result, reason) = if live_game.moves.len() < 2 {
+                (GameResult::Aborted, GameEndReason::Aborted)
+            } else {
+                (GameResult::win(!color), GameEndReason::Abandoned)
+            };
+            self.end_game(ctx, game_id, result, reason).await;
+        }
+    }
+
+    async fn handle_flag_check(&mut self, ctx: &mut HubState, game_id: LiveGameId) {
+        let live_game = match ctx.games.get(&game_id) {
+            Some(game) => game,
+            None => return, // game is already over
+        };
+        let turn = live_game.game.turn();
+        if !live_game.out_of_time(Instant::now()) {
+            return; // stale timer, a move was made in time
+        }
+        println!(
+            "HUB flag check, game id {:?} {:?} out of time",
+            game_id, turn
+        );
+
+        // no win on time without mating material
+        let result = if live_game.game.has_insufficient_material(!turn) {
+            GameResult::Draw
+        } else {
+            GameResult::win(!turn)
+        };
+        self.end_game(ctx, game_id, result, GameEndReason::Timeout)
+            .await;
+    }
+
+    async fn end_game(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        result: GameResult,
+        reason: GameEndReason,
+    ) {
+        let mut live_game = match ctx.games.remove(&game_id) {
+            Some(game) => game,
+            None => return,
+        };
+        println!("HUB game id {} over {:?} {:?}", game_id, result, reason);
+        if let (Some(writer), Some(_)) = (&self.writer, live_game.tc.days()) {
+            let _ = writer.send(Write::RemoveCorrespondence(game_id.to_string()));
+        }
+        let resp = WsMessage::GameEnd {
+            game_id,
+            result,
+            reason,
+        };
+        live_game.send_spectators(resp.clone());
+        send_lobby(ctx, WsMessage::GameEnded { game_id });
+
+        let mut names = [String::from("?"), String::from("?")];
+        let mut players = vec![];
+        for (uid, name) in [live_game.white, live_game.black].iter().zip(&mut names) {
+            if let Some(player) = ctx.players.get_mut(uid) {
+                let _ = player.respond_to.send(resp.clone()).await;
+                name.clone_from(&player.name);
+                players.push(Player::new(
+                    *uid,
+                    player.name.clone(),
+                    player.respond_to.clone(),
+                ));
+            }
+        }
+        for uid in ctx.members.leave(game_id) {
+            ctx.players.remove(&uid);
+        }
+
+        // humans may ask for a rematch for a while
+        if let Ok(players) = <[Player; 2]>::try_from(players) {
+            if players.iter().all(|player| player.uid >= 0) {
+                let since = Instant::now();
+                ctx.rematches.insert(
+                    game_id,
+                    Rematch {
+                        players,
+                        tc: live_game.tc,
+                        rated: live_game.rated,
+                        takebacks: live_game.takebacks,
+                        offer: None,
+                        since,
+                    },
+                );
+                let at = since + self.config.rematch_timeout;
+                self.schedule(at, Message::RematchExpired { game_id, since });
+            }
+        }
+
+        self.persist_game(live_game, names, result, reason);
+    }
+
+    async fn handle_rematch_offer(&mut self, ctx: &mut HubState, game_id: LiveGameId, uid: IdType) {
+        let rematch = match ctx.rematches.get_mut(&game_id) {
+            Some(rematch) if rematch.players.iter().any(|player| player.uid == uid) => rematch,
+            _ => return, // no recent game
+        };
+        match rematch.offer {
+            // crossing offers are an agreement
+            Some(offerer) if offerer != uid => self.start_rematch(ctx, game_id).await,
+            Some(_) => (), // already offered
+            None => {
+                let since = Instant::now();
+                rematch.offer = Some(uid);
+                rematch.since = since;
+                for player in rematch.players.iter().filter(|player| player.uid != uid) {
+                    let resp = WsMessage::RematchOffer { game_id };
+                    let _ = player.respond_to.send(resp).await;
+                }
+                let at = since + self.config.rematch_timeout;
+                self.schedule(at, Message::RematchExpired { game_id, since });
+            }
+        }
+    }
+
+    async fn handle_rematch_accept(
+        &mut self,
+        ctx: &mut HubState,
+        game_id: LiveGameId,
+        uid: IdType,
+    ) {
+        let rematch = match ctx.rematches.get(&game_id) {
+            Some(rematch) if rematch.players.iter().any(|player| player.uid == uid) => rematch,
+            _ => return, // no recent game
+        };
+        match rematch.offer {
+            Some(offerer) if offerer != uid => self.start_rematch(ctx, game_id).await,
+            _ => {
+                let player = rematch.players.iter().find(|player| player.uid == uid);
+                let reason = String::from("no rematch offer");
+                let resp = WsMessage::CommandRejected { reason };
+                let _ = player.expect("rematch of uid").respond_to.send(resp).await;
+            }
+        }
+    }
+
+    // Same players and time control, swapped colors
+    async fn start_rematch(&mut self, ctx: &mut HubState, game_id: LiveGameId) {
+        let rematch = ctx.rematches.remove(&game_id).expect("rematch of game id");
+        let [black, white] = rematch.players;
+        println!("HUB rematch of {}", game_id);
+
+        let now = Instant::now();
+        let mut live_game = LiveGame::new(rematch.tc, white.uid, black.uid, now);
+        live_game.rated = rematch.rated;
+        live_game.takebacks = rematch.takebacks;
+        self.create_game(ctx, [white, black], live_game, now).await;
+    }
+
+    async fn handle_challenge(
+        &mut self,
+        ctx: &mut HubState,
+        to_user: IdType,
+        msg: GamePreference,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+        name: String,
+    ) {
+        if to_user == uid || to_user < 0 {
+            let reason = String::from("can't challenge this user");
+            let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+            return;
+        }
+        if let Err(reason) = msg.tc.validate() {
+            let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+            return;
+        }
+        let pending = ctx.challenges.values();
+        let pending = pending.chain(ctx.unchecked_challenges.values());
+        if pending.filter(|c| c.from == uid).count() >= self.config.challenge_limit {
+            let reason = String::from("too many pending challenges");
+            let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+            return;
+        }
+        ctx.last_challenge_id += 1;
+        let challenge = Challenge {
+            id: ctx.last_challenge_id,
+            from: uid,
+            from_name: name,
+            respond_to,
+            to: to_user,
+            msg,
+        };
+        let db = match &self.db {
+            Some(db) => db.clone(),
+            None => return self.offer_challenge(ctx, challenge).await,
+        };
+
+        // the Db is asked off the Hub loop, the challenge waits for it
+        let (id, sender) = (challenge.id, self.sender.clone());
+        ctx.unchecked_challenges.insert(id, challenge);
+        tokio::spawn(async move {
+            let exists = match UserMac::get(&db, to_user).await {
+                Ok(user) => user.is_some(),
+                Err(e) => {
+                    eprintln!("HUB challenged user {} error {:?}", to_user, e);
+                    false
+                }
+            };
+            if let Some(sender) = sender.upgrade() {
+                let _ = sender.send(Message::ChallengeChecked { id, exists }).await;
+            }
+        });
+    }
+
+    async fn handle_challenge_checked(
+        &mut self,
+        ctx: &mut HubState,
+        id: ChallengeId,
+        exists: bool,
+    ) {
+        let challenge = match ctx.unchecked_challenges.remove(&id) {
+            Some(challenge) => challenge,
+            None => return,
+        };
+        if !exists {
+            let reason = String::from("no such user");
+            let resp = WsMessage::CommandRejected { reason };
+            let _ = challenge.respond_to.send(resp).await;
+            return;
+        }
+        self.offer_challenge(ctx, challenge).await;
+    }
+
+    async fn offer_challenge(&mut self, ctx: &mut HubState, challenge: Challenge) {
+        let msg = WsMessage::ChallengeOffer(challenge.info());
+        send_challengers(ctx, &challenge, msg).await;
+        let at = Instant::now() + self.config.challenge_timeout;
+        self.schedule(at, Message::ChallengeExpired { id: challenge.id });
+        ctx.challenges.insert(challenge.id, challenge);
+    }
+
+    async fn handle_challenge_accept(
+        &mut self,
+        ctx: &mut HubState,
+        id: ChallengeId,
+        respond_to: mpsc::Sender<WsMessage>,
+        uid: IdType,
+        name: String,
+    ) {
+        let from = match ctx.challenges.get(&id) {
+            Some(challenge) if challenge.to == uid => challenge.from,
+            _ => {
+                let reason = String::from("no such challenge");
+                let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+                return;
+            }
+        };
+        // the challenge stands until the challenger is back
+        let challenging_tx = match ctx.connections.get(&from) {
+            Some(tx) => tx.clone(),
+            None => {
+                let reason = String::from("challenger is offline");
+                let _ = respond_to.send(WsMessage::CommandRejected { reason }).await;
+                return;
+            }
+        };
+        let challenge = ctx.challenges.remove(&id).expect("challenge");
+        for uid in [challenge.from, challenge.to] {
+            while let Some(game_id) = rematch_of(ctx, uid) {
+                self.cancel_rematch(ctx, game_id).await;
+            }
+        }
+
+        let now = Instant::now();
+        let challenging = GameRequest {
+            id: 0,
+            msg: challenge.msg.clone(),
+            respond_to: challenging_tx,
+            uid: challenge.from,
+            name: challenge.from_name,
+            rating: None,
+            since: now,
+        };
+        let accepting = GameRequest {
+            id: 0,
+            msg: GamePreference {
+                color: ColorPreference::Any,
+                ..challenge.msg
+            },
+            respond_to,
+            uid,
+            name,
+            rating: None,
+            since: now,
+        };
+        self.load_ratings(ctx, Rated::Pairing(challenging, accepting))
+            .await;
+    }
+
+    async fn handle_challenge_decline(&mut self, ctx: &mut HubState, id: ChallengeId, uid: IdType) {
+        match ctx.challenges.get(&id) {
+            Some(challenge) if challenge.to == uid => (),
+            _ => return,
+        };
+        let challenge = ctx.challenges.remove(&id).expect("challenge");
+        let msg = WsMessage::ChallengeDecline { id };
+        send_challengers(ctx, &challenge, msg).await;
+    }
+
+    async fn handle_challenge_cancel(&mut self, ctx: &mut HubState, id: ChallengeId, uid: IdType) {
+        match ctx.challenges.get(&id) {
+            Some(challenge) if challenge.from == uid => (),
+            _ => return,
+        };
+        let challenge = ctx.challenges.remove(&id).expect("challenge");
+        let msg = WsMessage::ChallengeCancel { id };
+        send_challengers(ctx, &challenge, msg).await;
+    }
+
+    // The rematch is off, the offer expired or a player seeks elsewhere
+    async fn cancel_rematch(&mut self, ctx: &mut HubState, game_id: LiveGameId) {
+        if let Some(rematch) = ctx.rematches.remove(&game_id) {
+            if rematch.offer.is_some() {
+                for player in rematch.players {
+                    let resp = WsMessage::RematchExpired { game_id };
+                    let _ = player.respond_to.send(resp).await;
+                }
+            }
+        }
+    }
+
+    // Store the PGN and rate the game in the Db, off the Hub loop
+    fn persist_game(
+        &self,
+        live_game: LiveGame,
+        [white_name, black_name]: [String; 2],
+        result: GameResult,
+        reason: GameEndReason,
+    ) {
+        let (db, writer) = match (&self.db, &self.writer) {
+            (Some(db), Some(writer)) => (db.clone(), writer.clone()),
+            _ => return,
+        };
+        let (white, black) = (live_game.white, live_game.black);
+        // games where a side never moved are not rated
+        let category = live_game
+            .tc
+            .category()
+            .filter(|_| live_game.rated && live_game.moves.len() >= 2);
+        let mut pgn_game = PgnGame {
+            white: white_name,
+            black: black_name,
+            date: live_game.date,
+            tc: live_game.tc,
+            rated: live_game.rated,
+            result,
+            reason,
+            moves: live_game.moves,
+            game_id: Some(live_game.id),
+        };
+        let chat = live_game.chat;
+        tokio::spawn(async move {
+            // humans by their Db name, engines keep their own
+            for (uid, name) in [(white, &mut pgn_game.white), (black, &mut pgn_game.black)] {
+                if let Some(user) = user_name(&db, uid).await {
+                    *name = user;
+                }
+            }
+            let id = match GameMac::create(&db, &pgn_game.to_pgn()).await {
+                Ok(id) => id,
+                Err(e) => {
+                    eprintln!("HUB game {} vs {} store error {:?}", white, black, e);
+                    return;
+                }
+            };
+            println!("HUB game {} vs {} stored, id {}", white, black, id);
+            for line in chat {
+                let room = match line.room {
+                    ChatRoom::Spectators(_) => "spectators",
+                    _ => "game",
+                };
+                let res = ChatMac::create(&db, id, line.uid, &line.name, room, &line.text);
+                if let Err(e) = res.await {
+                    eprintln!("HUB game {} chat store error {:?}", id, e);
+                }
+            }
+
+            let (category, white_score) = match (category, result.white_score()) {
+                (Some(category), Some(score)) => (category, score),
+                _ => return,
+            };
+            let _ = writer.send(Write::Rate {
+                players: [white, black],
+                category,
+                white_score,
+                game: id,
+            });
+        });
+    }
+
+    // Reply with the rating history, off the Hub loop
+    fn send_ratings(&self, respond_to: mpsc::Sender<WsMessage>, uid: IdType) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            let history = match db.as_ref().map(|db| RatingMac::history(db, uid)) {
+                Some(history) => history.await,
+                None => Ok(vec![]),
+            };
+            let history = match history {
+                Ok(history) => history,
+                Err(e) => {
+                    eprintln!("HUB ratings of {} error {:?}", uid, e);
+                    return;
+                }
+            };
+            let ratings = history
+                .iter()
+                .filter_map(|row| {
+                    Some(RatingEntry {
+                        category: row.category.parse().ok()?,
+                        rating: row.into(),
+                        game: row.game,
+                    })
+                })
+                .collect();
+            let _ = respond_to.send(WsMessage::Ratings(ratings)).await;
+        });
+    }
+
+    // Correspondence games go on after a restart, players get them on connect
+    fn restore_correspondence(&mut self, ctx: &mut HubState, games: Vec<correspondence::Model>) {
+        // offline until they connect
+        let (offline, _) = mpsc::channel::<WsMessage>(1);
+        let now = Instant::now();
+        for stored in games {
+            let live_game = match LiveGame::restore(&stored, now) {
+                Some(game) => game,
+                None => {
+                    eprintln!("HUB correspondence game {} can't be restored", stored.id);
+                    continue;
+                }
+            };
+            println!("HUB correspondence game {} restored", live_game.id);
+            for (uid, name) in [
+                (stored.white, stored.white_name),
+                (stored.black, stored.black_name),
+            ] {
+                ctx.players
+                    .entry(uid)
+                    .or_insert_with(|| Player::new(uid, name, offline.clone()));
+            }
+            if ctx.games.contains_key(&live_game.id) {
+                continue; // already live
+            }
+            ctx.members
+                .join(live_game.id, [live_game.white, live_game.black]);
+            self.schedule_flag_check(&live_game);
+            ctx.games.insert(live_game.id, live_game);
+        }
+    }
+
+    async fn run(mut self) -> io::Result<()> {
+        let mut ctx = HubState {
+            requests: GameRequests::default(),
+            players: Players::default(),
+            members: Members::default(),
+            games: LiveGames::default(),
+            matchmake_scheduled: false,
+            last_engine_uid: 0,
+            rematches: HashMap::new(),
+            last_seek_id: 0,
+            lobby: vec![],
+            connections: HashMap::new(),
+            last_challenge_id: 0,
+            challenges: HashMap::new(),
+            unchecked_challenges: HashMap::new(),
+            chat_sent: HashMap::new(),
+            last_rating_load: 0,
+            rating_loads: HashMap::new(),
+            mutes: HashMap::new(),
+        };
+        loop {
+            let msg = tokio::select! {
+                msg = self.receiver.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                Some(msg) = self.engine_receiver.recv() => msg,
+            };
+            self.handle_message(&mut ctx, msg).await;
+        }
+        Ok(())
+    }
+}
+
+async fn send_player(ctx: &HubState, uid: IdType, msg: WsMessage) {
+    if let Some(player) = ctx.players.get(&uid) {
+        let _ = player.respond_to.send(msg).await;
+    }
+}
+
+// Members index agrees with the live games and players
+#[cfg(test)]
+fn check_members(ctx: &HubState) -> Result<(), String> {
+    for (game_id, live_game) in &ctx.games {
+        let uids = [live_game.white, live_game.black];
+        if ctx.members.players.get(game_id) != Some(&uids) {
+            return Err(format!("game {} players not indexed", game_id));
+        }
+        for uid in uids {
+            let games = ctx.members.games(uid);
+            if games.iter().filter(|id| *id == game_id).count() != 1 {
+                return Err(format!("uid {} not indexed once in {}", uid, game_id));
+            }
+            if !ctx.players.contains_key(&uid) {
+                return Err(format!("uid {} of game {} has no player", uid, game_id));
+            }
+        }
+    }
+    if let Some(game_id) = ctx
+        .members
+        .players
+        .keys()
+        .find(|id| !ctx.games.contains_key(id))
+    {
+        return Err(format!("game {} indexed after its end", game_id));
+    }
+    for (uid, games) in &ctx.members.games {
+        for game_id in games {
+            let seated = ctx
+                .members
+                .players
+                .get(game_id)
+                .map(|uids| uids.contains(uid));
+            if seated != Some(true) {
+                return Err(format!("uid {} indexed in {} without a seat", uid, game_id));
+            }
+        }
+        if games.is_empty() {
+            return Err(format!("uid {} indexed without games", uid));
+        }
+    }
+    if let Some(uid) = ctx
+        .players
+        .keys()
+        .find(|uid| !ctx.members.games.contains_key(uid))
+    {
+        return Err(format!("player {} left without games", uid));
+    }
+    Ok(())
+}
+
+// Color and opponent of a player in a live game
+fn live_player(ctx: &HubState, uid: IdType, game_id: LiveGameId) -> Option<(Color, IdType)> {
+    ctx.games.get(&game_id)?.seat(uid)
+}
+
+fn start_fen() -> String {
+    Fen::from_position(Chess::default(), EnPassantMode::Legal).to_string()
+}
+
+// What a spectator needs to follow a live game
+fn spectator_state(ctx: &HubState, game_id: LiveGameId) -> Option<SpectatorState> {
+    let live_game = ctx.games.get(&game_id)?;
+    let name = |uid| {
+        ctx.players
+            .get(uid)
+            .map(|player: &Player| player.name.clone())
+    };
+    let turn = live_game.game.turn();
+    let now = Instant::now();
+    Some(SpectatorState {
+        game_id,
+        white: live_game.white,
+        white_name: name(&live_game.white).unwrap_or_default(),
+        black: live_game.black,
+        black_name: name(&live_game.black).unwrap_or_default(),
+        fen: start_fen(),
+        moves: live_game.uci_moves(),
+        clock: live_game
+            .clock
+            .as_ref()
+            .map(|clock| clock.snapshot(turn, now)),
+    })
+}
+
+fn chat_member(ctx: &HubState, room: &ChatRoom, uid: IdType) -> bool {
+    match room {
+        ChatRoom::Game(game_id) => live_player(ctx, uid, *game_id).is_some(),
+        ChatRoom::Spectators(game_id) => match ctx.games.get(game_id) {
+            Some(live_game) => live_game.spectators.iter().any(|s| s.uid == uid),
+            None => false,
+        },
+        ChatRoom::Lobby => ctx.lobby.iter().any(|s| s.uid == uid),
+    }
+}
+
+// Both sides of a challenge, the challenged one if connected
+async fn send_challengers(ctx: &HubState, challenge: &Challenge, msg: WsMessage) {
+    let from = ctx.connections.get(&challenge.from);
+    let _ = from
+        .unwrap_or(&challenge.respond_to)
+        .send(msg.clone())
+        .await;
+    if let Some(to) = ctx.connections.get(&challenge.to) {
+        let _ = to.send(msg).await;
+    }
+}
+
+// A live game as listed in the lobby
+fn lobby_game(ctx: &HubState, game_id: LiveGameId) -> Option<LobbyGame> {
+    let live_game = ctx.games.get(&game_id)?;
+    let name = |uid| {
+        ctx.players
+            .get(uid)
+            .map(|player: &Player| player.name.clone())
+    };
+    Some(LobbyGame {
+        game_id,
+        white: live_game.white,
+        white_name: name(&live_game.white).unwrap_or_default(),
+        black: live_game.black,
+        black_name: name(&live_game.black).unwrap_or_default(),
+        tc: live_game.tc,
+        rated: live_game.rated,
+    })
+}
+
+fn send_lobby(ctx: &mut HubState, msg: WsMessage) {
+    send_subscribers(&mut ctx.lobby, &msg, |_| true);
+}
+
+// Without waiting, a count missed by a busy player is outdated by the next one
+fn send_spectator_count(ctx: &HubState, game_id: LiveGameId) {
+    let live_game = &ctx.games[&game_id];
+    let count = live_game.spectators.len();
+    for uid in [live_game.white, live_game.black] {
+        if let Some(player) = ctx.players.get(&uid) {
+            let _ = player
+                .respond_to
+                .try_send(WsMessage::Spectators { game_id, count });
+        }
+    }
+}
+
+// The newest finished game of uid open for a rematch
+fn rematch_of(ctx: &HubState, uid: IdType) -> Option<LiveGameId> {
+    ctx.rematches
+        .iter()
+        .filter(|(_, rematch)| rematch.players.iter().any(|player| player.uid == uid))
+        .max_by_key(|(_, rematch)| rematch.since)
+        .map(|(game_id, _)| *game_id)
+}
+
+async fn reject(ctx: &HubState, uid: IdType, reason: &str) {
+    println!("HUB uid {} command rejected, {}", uid, reason);
+    let reason = reason.to_owned();
+    send_player(ctx, uid, WsMessage::CommandRejected { reason }).await;
+}
+
+async fn user_name(db: &Db, uid: IdType) -> Option<String> {
+    if uid < 0 {
+        return None; // an engine
+    }
+    match UserMac::get(db, uid).await {
+        Ok(user) => user.map(|user| user.name),
+        Err(e) => {
+            eprintln!("HUB name of {} error {:?}", uid, e);
+            None
+        }
+    }
+}
+
+// Default rating for newcomers, on Db errors or without a Db
+async fn current_rating(db: Option<&Db>, uid: IdType, category: TimeCategory) -> Rating {
+    let db = match db {
+        Some(db) => db,
+        None => return Rating::default(),
+    };
+    match RatingMac::current(db, uid, category.as_str()).await {
+        Ok(rating) => rating.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("HUB rating of {} error {:?}", uid, e);
+            Rating::default()
+        }
+    }
+}
+
+// Glicko-2 update of both players, a rating period per game
+async fn rate_game(
+    db: &Db,
+    [white, black]: [IdType; 2],
+    category: TimeCategory,
+    white_score: f64,
+    game: IdType,
+) -> Result<(), crate::model::Error> {
+    let white_rating = current_rating(Some(db), white, category).await;
+    let black_rating = current_rating(Some(db), black, category).await;
+    let rated = [
+        (white, white_rating.rate(&[(black_rating, white_score)])),
+        (
+            black,
+            black_rating.rate(&[(white_rating, 1.0 - white_score)]),
+        ),
+    ];
+    for (uid, rating) in rated {
+        RatingMac::create(db, uid, category.as_str(), &rating, Some(game)).await?;
+    }
+    Ok(())
+}
+
+// Db writes applied one at a time in order, so that they don't race
+#[derive(Debug)]
+enum Write {
+    Rate {
+        players: [IdType; 2],
+        category: TimeCategory,
+        white_score: f64,
+        game: IdType,
+    },
+    SaveCorrespondence(correspondence::Model),
+    RemoveCorrespondence(String), // game id
+}
+
+fn spawn_writer(db: Db) -> mpsc::UnboundedSender<Write> {
+    let (writer, mut writes) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(write) = writes.recv().await {
+            match write {
+                Write::Rate {
+                    players,
+                    category,
+                    white_score,
+                    game,
+                } => {
+                    if let Err(e) = rate_game(&db, players, category, white_score, game).await {
+                        eprintln!("HUB game {} rating error {:?}", game, e);
+                    }
+                }
+                Write::SaveCorrespondence(stored) => {
+                    let id = stored.id.clone();
+                    if let Err(e) = CorrespondenceMac::save(&db, stored).await {
+                        eprintln!("HUB correspondence game {} store error {:?}", id, e);
+                    }
+                }
+                Write::RemoveCorrespondence(id) => {
+                    if let Err(e) = CorrespondenceMac::remove(&db, &id).await {
+                        eprintln!("HUB correspondence game {} remove error {:?}", id, e);
+                    }
+                }
+            }
+        }
+    });
+    writer
+}
+
+#[derive(Debug, Clone)]
+pub struct Handle {
+    pub sender: mpsc::Sender<Message>,
+}
+
+impl Handle {
+    pub fn new(db: Option<Db>, config: HubConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(256);
+        let (engines, engine_receiver) = mpsc::unbounded_channel();
+        let hub = Hub {
+            receiver,
+            sender: sender.downgrade(),
+            engines,
+            engine_receiver,
+            writer: db.clone().map(spawn_writer),
+            db,
+            config,
+        };
+        tokio::spawn(hub.run());
+        Handle { sender }
+    }
+
+    pub async fn send(&self, msg: Message) -> Result<(), mpsc::error::SendError<Message>> {
+        self.sender.send(msg).await
+    }
+
+    /// Hands the correspondence games stored in the Db to the Hub, after a restart
+    pub async fn restore_correspondence(&self, db: &Db) -> Result<(), crate::model::Error> {
+        let games = CorrespondenceMac::list(db).await?;
+        let _ = self.send(Message::RestoreCorrespondence { games }).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::engine::tests::fake_engine;
+    use crate::model::db::init_db;
+
+    // Test only
+    async fn send_game_request(handle: Handle, msg: GamePreference, uid: IdType) {
+        let (respond_to, mut receiver) = mpsc::channel::<WsMessage>(8);
+        let msg = Message::GameRequest {
+            msg,
+            respond_to,
+            uid,
+            name: format!("user {}", uid),
+        };
+
+        let _ = handle.send(msg).await;
+        let msg = receiver.recv().await.expect("Hub is dead");
+        println!("reply {:?}", msg);
+    }
+
+    #[tokio::test]
+    async fn chess_hub() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let mut jhs = vec![];
+        for i in 0..8 {
+            let handle = handle.clone();
+            let jh = tokio::spawn(async move {
+                send_game_request(handle, GamePreference::default(), i).await;
+            });
+            jhs.push(jh);
+        }
+        for jh in jhs {
+            let _ = jh.await;
+        }
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    // Hub's game membership index is consistent
+    async fn assert_members(handle: &Handle) {
+        let (respond_to, rx) = tokio::sync::oneshot::channel();
+        let _ = handle.send(Message::CheckMembers { respond_to }).await;
+        let checked = rx.await.expect("Hub is dead");
+        assert_eq!(checked, Ok(()));
+    }
+
+    async fn recv_game(receiver: &mut mpsc::Receiver<WsMessage>) -> (LiveGameId, WsColor) {
+        match receiver.recv().await.expect("Hub is dead") {
+            WsMessage::GameResponse { game_id, color } => (game_id, color),
+            msg => panic!("Expected game response, got {:?}", msg),
+        }
+    }
+
+    async fn recv_color(receiver: &mut mpsc::Receiver<WsMessage>) -> WsColor {
+        recv_game(receiver).await.1
+    }
+
+    // uid, its Ws Rx and the game it plays
+    type TestPlayer = (IdType, mpsc::Receiver<WsMessage>, LiveGameId);
+
+    // Pair two users, returns (white, black)
+    async fn start_game(
+        handle: &Handle,
+        msg: GamePreference,
+        uids: [IdType; 2],
+    ) -> Result<(TestPlayer, TestPlayer), Box<dyn std::error::Error>> {
+        let (tx0, mut rx0) = mpsc::channel::<WsMessage>(8);
+        let (tx1, mut rx1) = mpsc::channel::<WsMessage>(8);
+        for (uid, respond_to) in [(uids[0], tx0), (uids[1], tx1)] {
+            let msg = msg.clone();
+            handle
+                .send(Message::GameRequest {
+                    msg,
+                    respond_to,
+                    uid,
+                    name: format!("user {}", uid),
+                })
+                .await?;
+        }
+        let (game_id, color0) = recv_game(&mut rx0).await;
+        let color1 = recv_color(&mut rx1).await;
+        assert_ne!(color0, color1);
+        Ok(match color0 {
+            WsColor::White => ((uids[0], rx0, game_id), (uids[1], rx1, game_id)),
+            WsColor::Black => ((uids[1], rx1, game_id), (uids[0], rx0, game_id)),
+        })
+    }
+
+    #[tokio::test]
+    async fn chess_hub_move_relay() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        let ((white, mut white_rx, game_id), (black, mut black_rx, _)) =
+            start_game(&handle, msg, [100, 101]).await?;
+        assert_members(&handle).await;
+
+        // Black can't move first
+        let uci = String::from("e7e5");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: black,
+            })
+            .await?;
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::MoveRejected { .. }));
+
+        // Illegal move is rejected with the uci error
+        let uci = String::from("e2e5");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white,
+            })
+            .await?;
+        let msg = white_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::MoveRejected { .. }));
+
+        // Legal moves are relayed to the opponent
+        let uci = String::from("e2e4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white,
+            })
+            .await?;
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { uci, .. } if uci == "e2e4"));
+
+        let uci = String::from("e7e5");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: black,
+            })
+            .await?;
+        let msg = white_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { uci, .. } if uci == "e7e5"));
+        assert_members(&handle).await;
+
+        // Both players are dropped with the game
+        handle
+            .send(Message::Resign {
+                game_id,
+                uid: white,
+            })
+            .await?;
+        recv_game_end(&mut white_rx).await;
+        recv_game_end(&mut black_rx).await;
+        assert_members(&handle).await;
+        let uci = String::from("d2d4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white,
+            })
+            .await?;
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_clock_flag() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference {
+            tc: TimeControl {
+                main: 1,
+                incr: 0,
+                days: 0,
+            },
+            ..Default::default()
+        };
+        let ((white, mut white_rx, game_id), (_, mut black_rx, _)) =
+            start_game(&handle, msg, [100, 101]).await?;
+
+        // Both players get the initial clocks
+        for rx in [&mut white_rx, &mut black_rx] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(
+                matches!(msg, WsMessage::Clock { clock: c, .. } if c.white == 1000 && c.black == 1000)
+            );
+        }
+
+        let uci = String::from("e2e4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white,
+            })
+            .await?;
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { .. }));
+        for rx in [&mut white_rx, &mut black_rx] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::Clock { clock: c, .. } if c.black == 1000));
+        }
+
+        // Black doesn't move and loses on time
+        for rx in [&mut white_rx, &mut black_rx] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(
+                msg,
+                WsMessage::GameEnd {
+                    result: GameResult::WhiteWins,
+                    reason: GameEndReason::Timeout,
+                    ..
+                }
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Play the moves alternately, returns the next message of both players
+    async fn play_moves(
+        handle: &Handle,
+        white: &mut TestPlayer,
+        black: &mut TestPlayer,
+        moves: &[&str],
+    ) -> Result<(WsMessage, WsMessage), Box<dyn std::error::Error>> {
+        make_moves(handle, white, black, moves).await?;
+        let white_msg = recv_no_clock(&mut white.1).await;
+        let black_msg = recv_no_clock(&mut black.1).await;
+        Ok((white_msg, black_msg))
+    }
+
+    // Play moves from the starting position, each relayed to the opponent
+    async fn make_moves(
+        handle: &Handle,
+        white: &mut TestPlayer,
+        black: &mut TestPlayer,
+        moves: &[&str],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (i, uci) in moves.iter().enumerate() {
+            let (uid, rx) = match i % 2 {
+                0 => (white.0, &mut black.1),
+                _ => (black.0, &mut white.1),
+            };
+            let (game_id, uci) = (white.2, uci.to_string());
+            handle.send(Message::Move { game_id, uci, uid }).await?;
+            let msg = recv_no_clock(rx).await;
+            assert!(matches!(msg, WsMessage::Move { .. }));
+        }
+        Ok(())
+    }
+
+    // Next message, skipping clock updates of timed games
+    async fn recv_no_clock(receiver: &mut mpsc::Receiver<WsMessage>) -> WsMessage {
+        loop {
+            match receiver.recv().await.expect("Hub is dead") {
+                WsMessage::Clock { .. } => continue,
+                msg => return msg,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn chess_hub_requester_moves() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let mut waiting = request(&handle, seek(ColorPreference::Black, 0), 100).await?;
+        let mut requester = request(&handle, seek(ColorPreference::White, 0), 101).await?;
+        let (game_id, color) = recv_game(&mut requester).await;
+        assert_eq!(color, WsColor::White);
+        assert_eq!(recv_game(&mut waiting).await, (game_id, WsColor::Black));
+        assert_members(&handle).await;
+
+        // The request completing the pair is registered as a player too
+        for (uid, uci, rx) in [(101, "e2e4", &mut waiting), (100, "e7e5", &mut requester)] {
+            let uci = String::from(uci);
+            handle.send(Message::Move { game_id, uci, uid }).await?;
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::Move { .. }), "got {:?}", msg);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_game_end_checkmate() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let handle = Handle::new(Some(db.clone()), HubConfig::default());
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let game_id = white.2;
+
+        let moves = ["f2f3", "e7e5", "g2g4", "d8h4"];
+        let msgs = play_moves(&handle, &mut white, &mut black, &moves).await?;
+        for msg in [msgs.0, msgs.1] {
+            assert!(matches!(
+                msg,
+                WsMessage::GameEnd {
+                    game_id: ended,
+                    result: GameResult::BlackWins,
+                    reason: GameEndReason::Checkmate,
+                } if ended == game_id
+            ));
+        }
+
+        // The game is persisted in the background
+        let tag = format!("[GameId \"{}\"]", game_id);
+        for _ in 0..50 {
+            let games = GameMac::list(&db).await?;
+            if let Some(game) = games.iter().find(|game| game.pgn.contains(&tag)) {
+                let pgn = &game.pgn;
+                println!("{}", pgn);
+                assert!(pgn.contains("[Result \"0-1\"]"));
+                assert!(pgn.ends_with("1. f3 e5 2. g4 Qh4# 0-1\n"));
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("game not stored");
+    }
+
+    #[tokio::test]
+    async fn chess_hub_game_end_repetition() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8"].repeat(2);
+        let msgs = play_moves(&handle, &mut white, &mut black, &moves).await?;
+        for msg in [msgs.0, msgs.1] {
+            assert!(matches!(
+                msg,
+                WsMessage::GameEnd {
+                    result: GameResult::Draw,
+                    reason: GameEndReason::ThreefoldRepetition,
+                    ..
+                }
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn short_grace() -> HubConfig {
+        HubConfig {
+            reconnect_grace: Duration::from_millis(100),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn chess_hub_disconnect_abort() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, short_grace());
+        let msg = GamePreference::default();
+        let ((white, mut white_rx, _), (_, mut black_rx, _)) =
+            start_game(&handle, msg, [100, 101]).await?;
+
+        // Another connection of White closing is ignored
+        let (respond_to, _) = mpsc::channel::<WsMessage>(8);
+        let uid = white;
+        handle
+            .send(Message::WsDisconnect { respond_to, uid })
+            .await?;
+
+        // White's playing connection closes before any move
+        let (respond_to, _) = mpsc::channel::<WsMessage>(8);
+        handle
+            .send(Message::WsConnect {
+                respond_to: respond_to.clone(),
+                uid,
+            })
+            .await?;
+        handle
+            .send(Message::WsDisconnect { respond_to, uid })
+            .await?;
+
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::OpponentDisconnected { .. }));
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(
+            msg,
+            WsMessage::GameEnd {
+                result: GameResult::Aborted,
+                reason: GameEndReason::Aborted,
+                ..
+            }
+        ));
+        assert!(white_rx.recv().await.is_none());
+
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_disconnect_forfeit() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, short_grace());
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let moves = ["e2e4", "e7e5"];
+        for (i, uci) in moves.iter().enumerate() {
+            let (uid, rx) = match i % 2 {
+                0 => (white.0, &mut black.1),
+                _ => (black.0, &mut white.1),
+            };
+            let (game_id, uci) = (white.2, uci.to_string());
+            handle.send(Message::Move { game_id, uci, uid }).await?;
+            assert!(matches!(rx.recv().await, Some(WsMessage::Move { .. })));
+        }
+
+        // Black's socket closes for good
+        let (respond_to, _) = mpsc::channel::<WsMessage>(8);
+        let uid = black.0;
+        handle
+            .send(Message::WsConnect {
+                respond_to: respond_to.clone(),
+                uid,
+            })
+            .await?;
+        handle
+            .send(Message::WsDisconnect { respond_to, uid })
+            .await?;
+
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::OpponentDisconnected { .. }));
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(
+            msg,
+            WsMessage::GameEnd {
+                result: GameResult::WhiteWins,
+                reason: GameEndReason::Abandoned,
+                ..
+            }
+        ));
+
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_reconnect() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, short_grace());
+        let msg = GamePreference::default();
+        let ((white, white_rx, game_id), (black, mut black_rx, _)) =
+            start_game(&handle, msg, [100, 101]).await?;
+        let uci = String::from("e2e4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white,
+            })
+            .await?;
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { .. }));
+
+        // White's socket closes, a new one connects in time
+        let (respond_to, _) = mpsc::channel::<WsMessage>(8);
+        let uid = white;
+        handle
+            .send(Message::WsConnect {
+                respond_to: respond_to.clone(),
+                uid,
+            })
+            .await?;
+        handle
+            .send(Message::WsDisconnect { respond_to, uid })
+            .await?;
+        drop(white_rx);
+        let (respond_to, mut white_rx) = mpsc::channel::<WsMessage>(8);
+        handle.send(Message::WsConnect { respond_to, uid }).await?;
+
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::OpponentDisconnected { .. }));
+        let msg = black_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::OpponentReconnected { .. }));
+
+        // The new connection gets the game state, and on request
+        handle.send(Message::GetGameState { uid: white }).await?;
+        for _ in 0..2 {
+            let state = match white_rx.recv().await.expect("Hub is dead") {
+                WsMessage::GameState(state) => state,
+                msg => panic!("Expected game state, got {:?}", msg),
+            };
+            assert_eq!(state.moves, vec![String::from("e2e4")]);
+            assert_eq!(state.color, WsColor::White);
+            assert_eq!(state.opponent, black);
+            assert_eq!(state.opponent_name, format!("user {}", black));
+            assert!(state.clock.is_none());
+        }
+
+        // The game goes on past the grace period on the new connection
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let uci = String::from("e7e5");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: black,
+            })
+            .await?;
+        let msg = white_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { .. }));
+
+        Ok(())
+    }
+
+    fn seek(color: ColorPreference, main: u32) -> GamePreference {
+        GamePreference {
+            color,
+            tc: TimeControl {
+                main,
+                incr: 0,
+                days: 0,
+            },
+            ..Default::default()
+        }
+    }
+
+    async fn request(
+        handle: &Handle,
+        msg: GamePreference,
+        uid: IdType,
+    ) -> Result<mpsc::Receiver<WsMessage>, Box<dyn std::error::Error>> {
+        let (respond_to, receiver) = mpsc::channel::<WsMessage>(8);
+        let name = format!("user {}", uid);
+        handle
+            .send(Message::GameRequest {
+                msg,
+                respond_to,
+                uid,
+                name,
+            })
+            .await?;
+        Ok(receiver)
+    }
+
+    async fn assert_unpaired(receiver: &mut mpsc::Receiver<WsMessage>) {
+        let msg = tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await;
+        assert!(msg.is_err(), "Unexpected {:?}", msg);
+    }
+
+    #[tokio::test]
+    async fn chess_hub_matchmaking_color() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+
+        // Two White seekers can't play each other
+        let mut rx0 = request(&handle, seek(ColorPreference::White, 300), 100).await?;
+        let mut rx1 = request(&handle, seek(ColorPreference::White, 300), 101).await?;
+        assert_unpaired(&mut rx0).await;
+        assert_unpaired(&mut rx1).await;
+
+        // Any pairs with the oldest White seek and gets Black
+        let mut rx2 = request(&handle, seek(ColorPreference::Any, 300), 102).await?;
+        assert_eq!(recv_color(&mut rx0).await, WsColor::White);
+        assert_eq!(recv_color(&mut rx2).await, WsColor::Black);
+        assert_unpaired(&mut rx1).await;
+
+        // Black is compatible with White
+        let mut rx3 = request(&handle, seek(ColorPreference::Black, 300), 103).await?;
+        assert_eq!(recv_color(&mut rx1).await, WsColor::White);
+        assert_eq!(recv_color(&mut rx3).await, WsColor::Black);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_matchmaking_time_control() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+
+        let mut rx0 = request(&handle, seek(ColorPreference::Any, 300), 100).await?;
+        let mut rx1 = request(&handle, seek(ColorPreference::Any, 60), 101).await?;
+        assert_unpaired(&mut rx0).await;
+        assert_unpaired(&mut rx1).await;
+
+        // Close enough time control pairs and the waiting seek's one is played
+        let mut rx2 = request(&handle, seek(ColorPreference::Any, 290), 102).await?;
+        recv_color(&mut rx0).await;
+        recv_color(&mut rx2).await;
+        let msg = rx2.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Clock { clock: c, .. } if c.white == 300_000));
+        assert_unpaired(&mut rx1).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_matchmaking_two_tabs() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+
+        let mut rx0 = request(&handle, GamePreference::default(), 100).await?;
+        let mut rx1 = request(&handle, GamePreference::default(), 100).await?;
+        assert_unpaired(&mut rx0).await;
+        assert_unpaired(&mut rx1).await;
+
+        // Someone else gets the oldest tab
+        let mut rx2 = request(&handle, GamePreference::default(), 101).await?;
+        recv_color(&mut rx0).await;
+        recv_color(&mut rx2).await;
+        assert_unpaired(&mut rx1).await;
+
+        Ok(())
+    }
+
+    fn random_uid() -> IdType {
+        rand::random::<u32>().into()
+    }
+
+    #[tokio::test]
+    async fn chess_hub_matchmaking_rating() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let config = HubConfig {
+            rating_window_growth: 200.0,
+            matchmaking_tick: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let handle = Handle::new(Some(db.clone()), config);
+        let uids: Vec<IdType> = (0..4).map(|_| random_uid()).collect();
+        for (uid, rating) in uids.iter().zip([1500.0, 1900.0, 1540.0, 1700.0]) {
+            let rating = Rating {
+                rating,
+                ..Default::default()
+            };
+            RatingMac::create(&db, *uid, "blitz", &rating, None).await?;
+        }
+        let blitz = || seek(ColorPreference::Any, 300);
+        let wait = Duration::from_secs(5);
+
+        // The closest rated is paired, not the oldest
+        let mut rx0 = request(&handle, blitz(), uids[0]).await?;
+        let mut rx1 = request(&handle, blitz(), uids[1]).await?;
+        let mut rx2 = request(&handle, blitz(), uids[2]).await?;
+        tokio::time::timeout(wait, recv_color(&mut rx0)).await?;
+        tokio::time::timeout(wait, recv_color(&mut rx2)).await?;
+        assert_unpaired(&mut rx1).await;
+
+        // 200 points apart, paired once the window has widened
+        let mut rx3 = request(&handle, blitz(), uids[3]).await?;
+        tokio::time::timeout(wait, recv_color(&mut rx1)).await?;
+        tokio::time::timeout(wait, recv_color(&mut rx3)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_rated_game() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let handle = Handle::new(Some(db.clone()), HubConfig::default());
+        let msg = seek(ColorPreference::Any, 300);
+        let (mut white, mut black) = start_game(&handle, msg, [random_uid(), random_uid()]).await?;
+
+        let moves = ["f2f3", "e7e5", "g2g4", "d8h4"];
+        let msgs = play_moves(&handle, &mut white, &mut black, &moves).await?;
+        assert!(matches!(msgs.0, WsMessage::GameEnd { .. }));
+
+        // Both ratings are updated in the background
+        let mut rated = None;
+        for _ in 0..50 {
+            let white_rating = RatingMac::current(&db, white.0, "blitz").await?;
+            let black_rating = RatingMac::current(&db, black.0, "blitz").await?;
+            if let (Some(w), Some(b)) = (white_rating, black_rating) {
+                rated = Some((w, b));
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let (white_rating, black_rating) = rated.expect("game not rated");
+        assert!(white_rating.rating < 1500.0);
+        assert!(black_rating.rating > 1500.0);
+
+        // The winner's history
+        let (respond_to, mut receiver) = mpsc::channel::<WsMessage>(8);
+        let uid = black.0;
+        handle.send(Message::GetRatings { respond_to, uid }).await?;
+        match receiver.recv().await.expect("Hub is dead") {
+            WsMessage::Ratings(ratings) => {
+                assert_eq!(ratings.len(), 1);
+                assert_eq!(ratings[0].category, TimeCategory::Blitz);
+                assert_eq!(ratings[0].rating, black_rating);
+                assert!(ratings[0].game.is_some());
+            }
+            msg => panic!("Expected ratings, got {:?}", msg),
+        }
+
+        Ok(())
+    }
+
+    fn engine_seek(engine: &str, level: Option<u8>) -> GamePreference {
+        GamePreference {
+            color: ColorPreference::White,
+            opponent: OpponentPreference::Engine(String::from(engine)),
+            level,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn chess_hub_engine_game() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = HubConfig::default();
+        let engine = fake_engine(&["e7e5", "d8h4"]);
+        config.engines = Engines::from([(String::from("fake"), engine)]);
+        let handle = Handle::new(None, config);
+
+        let msg = engine_seek("fake", Some(1));
+        let mut rx = request(&handle, msg, 100).await?;
+        let (game_id, color) = recv_game(&mut rx).await;
+        assert_eq!(color, WsColor::White);
+        let msg = Message::TakebackRequest { game_id, uid: 100 };
+        handle.send(msg).await?;
+        let msg = recv_no_clock(&mut rx).await;
+        assert!(
+            matches!(msg, WsMessage::CommandRejected { reason } if reason == "takebacks are disabled")
+        );
+
+        // The engine answers through the Hub like a human opponent
+        for (uci, reply) in [("f2f3", "e7e5"), ("g2g4", "d8h4")] {
+            let uci = String::from(uci);
+            handle
+                .send(Message::Move {
+                    game_id,
+                    uci,
+                    uid: 100,
+                })
+                .await?;
+            let msg = recv_no_clock(&mut rx).await;
+            assert!(matches!(msg, WsMessage::Move { uci, .. } if uci == reply));
+        }
+        let msg = recv_no_clock(&mut rx).await;
+        assert!(matches!(
+            msg,
+            WsMessage::GameEnd {
+                result: GameResult::BlackWins,
+                reason: GameEndReason::Checkmate,
+                ..
+            }
+        ));
+
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_engine_unavailable() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = HubConfig::default();
+        let engine = fake_engine(&[]);
+        config.engines = Engines::from([(String::from("fake"), engine)]);
+        let handle = Handle::new(None, config);
+
+        // Configured engines are listed with their number of levels
+        let (respond_to, mut rx) = mpsc::channel::<WsMessage>(8);
+        handle.send(Message::GetEngines { respond_to }).await?;
+        let msg = rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Engines(e) if e.len() == 1 && e[0].levels == 1));
+
+        for msg in [engine_seek("Lc0", None), engine_seek("fake", Some(2))] {
+            let mut rx = request(&handle, msg, 100).await?;
+            let msg = rx.recv().await.expect("Hub is dead");
+            println!("reply {:?}", msg);
+            assert!(matches!(msg, WsMessage::GameRequestRejected { .. }));
+        }
+
+        Ok(())
+    }
+
+    async fn recv_game_end(
+        receiver: &mut mpsc::Receiver<WsMessage>,
+    ) -> (GameResult, GameEndReason) {
+        match recv_no_clock(receiver).await {
+            WsMessage::GameEnd { result, reason, .. } => (result, reason),
+            msg => panic!("Expected game end, got {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn chess_hub_simultaneous_games() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        // User 100 plays 101 and 102 at once, on the newest connection
+        let (white, black) = start_game(&handle, msg.clone(), [100, 101]).await?;
+        let (mut first, _) = if white.0 == 101 {
+            (white, black)
+        } else {
+            (black, white)
+        };
+        let (white, black) = start_game(&handle, msg, [100, 102]).await?;
+        let (second, mut user) = if white.0 == 102 {
+            (white, black)
+        } else {
+            (black, white)
+        };
+        assert_ne!(first.2, second.2);
+        assert_members(&handle).await;
+
+        // Ending one game leaves the other one going
+        let game_id = first.2;
+        handle.send(Message::Resign { game_id, uid: 101 }).await?;
+        match recv_no_clock(&mut first.1).await {
+            WsMessage::GameEnd { game_id, .. } => assert_eq!(game_id, first.2),
+            msg => panic!("Expected game end, got {:?}", msg),
+        }
+        recv_game_end(&mut user.1).await;
+        assert_members(&handle).await;
+
+        handle.send(Message::GetGameState { uid: 100 }).await?;
+        match user.1.recv().await.expect("Hub is dead") {
+            WsMessage::GameState(state) => {
+                assert_eq!(state.game_id, second.2);
+                assert_eq!(state.opponent, 102);
+            }
+            msg => panic!("Expected game state, got {:?}", msg),
+        }
+        let game_id = second.2;
+        handle.send(Message::Resign { game_id, uid: 102 }).await?;
+        recv_game_end(&mut user.1).await;
+
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_resign() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        let ((_, mut white_rx, game_id), (black, mut black_rx, _)) =
+            start_game(&handle, msg, [100, 101]).await?;
+
+        handle
+            .send(Message::Resign {
+                game_id,
+                uid: black,
+            })
+            .await?;
+        for rx in [&mut white_rx, &mut black_rx] {
+            let end = recv_game_end(rx).await;
+            assert_eq!(end, (GameResult::WhiteWins, GameEndReason::Resignation));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_draw_offer() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let game_id = white.2;
+
+        // Offer and decline
+        handle
+            .send(Message::OfferDraw {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let msg = black.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::OfferDraw { .. }));
+        handle
+            .send(Message::DeclineDraw {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::DeclineDraw { .. }));
+        handle
+            .send(Message::AcceptDraw {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        let msg = black.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        // The offerer's move withdraws the offer
+        handle
+            .send(Message::OfferDraw {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let msg = black.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::OfferDraw { .. }));
+        let uci = String::from("e2e4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white.0,
+            })
+            .await?;
+        let msg = black.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { .. }));
+        let msg = black.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::DrawOfferWithdrawn { .. }));
+        handle
+            .send(Message::AcceptDraw {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        let msg = black.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        // Offer and accept
+        handle
+            .send(Message::OfferDraw {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::OfferDraw { .. }));
+        handle.send(Message::GetGameState { uid: white.0 }).await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::GameState(s) if s.draw_offer == Some(WsColor::Black)));
+        handle
+            .send(Message::AcceptDraw {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        for rx in [&mut white.1, &mut black.1] {
+            let end = recv_game_end(rx).await;
+            assert_eq!(end, (GameResult::Draw, GameEndReason::DrawAgreement));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_abort() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg.clone(), [100, 101]).await?;
+        let game_id = white.2;
+
+        // Black hasn't moved yet
+        handle
+            .send(Message::Move {
+                game_id,
+                uci: String::from("e2e4"),
+                uid: white.0,
+            })
+            .await?;
+        black.1.recv().await.expect("Hub is dead");
+        handle
+            .send(Message::Abort {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        for rx in [&mut white.1, &mut black.1] {
+            let end = recv_game_end(rx).await;
+            assert_eq!(end, (GameResult::Aborted, GameEndReason::Aborted));
+        }
+
+        // Too late once both sides have moved
+        let (mut white, mut black) = start_game(&handle, msg, [102, 103]).await?;
+        let game_id = white.2;
+        handle
+            .send(Message::Move {
+                game_id,
+                uci: String::from("e2e4"),
+                uid: white.0,
+            })
+            .await?;
+        black.1.recv().await.expect("Hub is dead");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci: String::from("e7e5"),
+                uid: black.0,
+            })
+            .await?;
+        white.1.recv().await.expect("Hub is dead");
+        handle
+            .send(Message::Abort {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_takeback() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = seek(ColorPreference::Any, 60);
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let game_id = white.2;
+        make_moves(&handle, &mut white, &mut black, &["e2e4", "e7e5", "g1f3"]).await?;
+
+        // Declined
+        handle
+            .send(Message::TakebackRequest {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let msg = recv_no_clock(&mut black.1).await;
+        assert!(matches!(msg, WsMessage::TakebackRequest { .. }));
+        handle
+            .send(Message::TakebackDecline {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        let msg = recv_no_clock(&mut white.1).await;
+        assert!(matches!(msg, WsMessage::TakebackDecline { .. }));
+
+        // Accepted, White's last move is taken back, White moves again
+        handle
+            .send(Message::TakebackRequest {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        recv_no_clock(&mut black.1).await;
+        handle
+            .send(Message::TakebackAccept {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        for rx in [&mut white.1, &mut black.1] {
+            match recv_no_clock(rx).await {
+                WsMessage::GameState(state) => {
+                    assert_eq!(state.moves, ["e2e4", "e7e5"]);
+                    assert!(state.clock.unwrap().black > 59_000);
+                }
+                msg => panic!("Expected game state, got {:?}", msg),
+            }
+        }
+        let uci = String::from("d2d4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white.0,
+            })
+            .await?;
+        let msg = recv_no_clock(&mut black.1).await;
+        assert!(matches!(msg, WsMessage::Move { uci, .. } if uci == "d2d4"));
+
+        // On Black's turn both plies are taken back
+        handle
+            .send(Message::TakebackRequest {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        recv_no_clock(&mut white.1).await;
+        handle
+            .send(Message::TakebackAccept {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        match recv_no_clock(&mut white.1).await {
+            WsMessage::GameState(state) => assert_eq!(state.moves, ["e2e4"]),
+            msg => panic!("Expected game state, got {:?}", msg),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_takeback_disabled() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference {
+            disable_takebacks: true,
+            ..Default::default()
+        };
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let game_id = white.2;
+        let uci = String::from("e2e4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: white.0,
+            })
+            .await?;
+        black.1.recv().await.expect("Hub is dead");
+
+        handle
+            .send(Message::TakebackRequest {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        Ok(())
+    }
+
+    // Start a game and resign it, returns (white, black)
+    async fn finished_game(
+        handle: &Handle,
+        uids: [IdType; 2],
+    ) -> Result<(TestPlayer, TestPlayer), Box<dyn std::error::Error>> {
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(handle, msg, uids).await?;
+        let game_id = white.2;
+        handle
+            .send(Message::Resign {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        recv_game_end(&mut white.1).await;
+        recv_game_end(&mut black.1).await;
+        Ok((white, black))
+    }
+
+    #[tokio::test]
+    async fn chess_hub_rematch() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let (mut white, mut black) = finished_game(&handle, [100, 101]).await?;
+        let game_id = white.2;
+
+        handle
+            .send(Message::RematchAccept {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        handle
+            .send(Message::RematchOffer {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::RematchOffer { .. }));
+        handle
+            .send(Message::RematchAccept {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+
+        // Colors are swapped, in a new game
+        let (game_id, color) = recv_game(&mut white.1).await;
+        assert_ne!(game_id, white.2);
+        assert_eq!(color, WsColor::Black);
+        assert_eq!(recv_game(&mut black.1).await, (game_id, WsColor::White));
+        let uci = String::from("e2e4");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: black.0,
+            })
+            .await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { .. }));
+
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_rematch_expiry() -> Result<(), Box<dyn std::error::Error>> {
+        let config = HubConfig {
+            rematch_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let handle = Handle::new(None, config);
+
+        // The offer times out
+        let (mut white, mut black) = finished_game(&handle, [100, 101]).await?;
+        let game_id = white.2;
+        handle
+            .send(Message::RematchOffer {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let msg = black.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::RematchOffer { .. }));
+        for rx in [&mut white.1, &mut black.1] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::RematchExpired { .. }));
+        }
+        handle
+            .send(Message::RematchAccept {
+                game_id,
+                uid: black.0,
+            })
+            .await?;
+        let msg = tokio::time::timeout(Duration::from_millis(100), black.1.recv()).await;
+        assert!(!matches!(msg, Ok(Some(_))), "Unexpected {:?}", msg);
+
+        // The opponent seeks elsewhere
+        let (mut white, mut black) = finished_game(&handle, [100, 101]).await?;
+        let game_id = white.2;
+        handle
+            .send(Message::RematchOffer {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        black.1.recv().await.expect("Hub is dead");
+        let _black_seek = request(&handle, GamePreference::default(), black.0).await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::RematchExpired { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_spectator_lagging() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let game_id = white.2;
+
+        // Room for the spectator state only, never read
+        let (respond_to, mut spectator) = mpsc::channel::<WsMessage>(1);
+        let uid = 102;
+        let msg = Message::Spectate {
+            game_id,
+            respond_to,
+            uid,
+        };
+        handle.send(msg).await?;
+        for rx in [&mut white.1, &mut black.1] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::Spectators { count: 1, .. }));
+        }
+
+        // The game goes on without waiting for it, it is dropped
+        let moves = make_moves(&handle, &mut white, &mut black, &["e2e4", "e7e5", "g1f3"]);
+        tokio::time::timeout(Duration::from_secs(5), moves).await??;
+        let msg = spectator.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::SpectatorState(_)));
+        assert!(spectator.recv().await.is_none());
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_spectate() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let game_id = white.2;
+
+        // Unknown games can't be watched
+        let (respond_to, mut spectator) = mpsc::channel::<WsMessage>(8);
+        let msg = Message::Spectate {
+            game_id: LiveGameId::new_v4(),
+            respond_to: respond_to.clone(),
+            uid: 102,
+        };
+        handle.send(msg).await?;
+        let msg = spectator.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        make_moves(&handle, &mut white, &mut black, &["e2e4"]).await?;
+        let msg = Message::Spectate {
+            game_id,
+            respond_to: respond_to.clone(),
+            uid: 102,
+        };
+        handle.send(msg).await?;
+        match spectator.recv().await.expect("Hub is dead") {
+            WsMessage::SpectatorState(state) => {
+                assert_eq!(state.game_id, game_id);
+                assert_eq!(state.white_name, format!("user {}", white.0));
+                assert_eq!(state.moves, vec![String::from("e2e4")]);
+            }
+            msg => panic!("Expected spectator state, got {:?}", msg),
+        }
+        for rx in [&mut white.1, &mut black.1] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::Spectators { count: 1, .. }));
+        }
+
+        // Moves and the game end are relayed to the spectator
+        let uci = String::from("e7e5");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: black.0,
+            })
+            .await?;
+        let msg = spectator.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { uci, .. } if uci == "e7e5"));
+        white.1.recv().await.expect("Hub is dead");
+
+        // A closed spectator socket is dropped from the game
+        let uid = 102;
+        handle
+            .send(Message::WsDisconnect { respond_to, uid })
+            .await?;
+        for rx in [&mut white.1, &mut black.1] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::Spectators { count: 0, .. }));
+        }
+
+        let (respond_to, mut spectator) = mpsc::channel::<WsMessage>(8);
+        handle
+            .send(Message::Spectate {
+                game_id,
+                respond_to,
+                uid: 102,
+            })
+            .await?;
+        spectator.recv().await.expect("Hub is dead");
+        handle
+            .send(Message::Resign {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        let end = recv_game_end(&mut spectator).await;
+        assert_eq!(end, (GameResult::BlackWins, GameEndReason::Resignation));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_lobby_lagging() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        // Room for the lobby state only, never read
+        let (respond_to, mut lobby) = mpsc::channel::<WsMessage>(1);
+        let uid = 102;
+        handle.send(Message::Lobby { respond_to, uid }).await?;
+
+        // Seeking goes on without waiting for it, it is dropped
+        let mut seekers = vec![];
+        for uid in [100, 101] {
+            seekers.push(request(&handle, seek(ColorPreference::Black, 0), uid).await?);
+        }
+        tokio::time::timeout(Duration::from_secs(5), assert_members(&handle)).await?;
+        let msg = lobby.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::LobbyState { .. }));
+        assert!(lobby.recv().await.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_lobby() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let (respond_to, mut lobby) = mpsc::channel::<WsMessage>(8);
+        handle
+            .send(Message::Lobby {
+                respond_to,
+                uid: 102,
+            })
+            .await?;
+        let msg = lobby.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::LobbyState { seeks, .. } if seeks.is_empty()));
+
+        let mut seeker = request(&handle, seek(ColorPreference::Black, 0), 100).await?;
+        let id = match lobby.recv().await.expect("Hub is dead") {
+            WsMessage::SeekCreated(seek) if seek.uid == 100 => seek.id,
+            msg => panic!("Expected seek created, got {:?}", msg),
+        };
+
+        // One's own seek can't be accepted
+        let (respond_to, mut rx) = mpsc::channel::<WsMessage>(8);
+        let name = String::from("user 100");
+        let msg = Message::AcceptSeek {
+            id,
+            respond_to,
+            uid: 100,
+            name,
+        };
+        handle.send(msg).await?;
+        let msg = rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        let (respond_to, mut accepter) = mpsc::channel::<WsMessage>(8);
+        let name = String::from("user 101");
+        let msg = Message::AcceptSeek {
+            id,
+            respond_to,
+            uid: 101,
+            name,
+        };
+        handle.send(msg).await?;
+        let (game_id, color) = recv_game(&mut seeker).await;
+        assert_eq!(color, WsColor::Black);
+        assert_eq!(recv_color(&mut accepter).await, WsColor::White);
+        let msg = lobby.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::SeekRemoved { id: removed } if removed == id));
+        match lobby.recv().await.expect("Hub is dead") {
+            WsMessage::GameStarted(game) => {
+                assert_eq!(game.game_id, game_id);
+                assert_eq!((game.white, game.black), (101, 100));
+                assert_eq!(game.black_name, "user 100");
+            }
+            msg => panic!("Expected game started, got {:?}", msg),
+        }
+
+        // Late subscribers see the game, the seek is gone
+        let (respond_to, mut late) = mpsc::channel::<WsMessage>(8);
+        handle
+            .send(Message::Lobby {
+                respond_to,
+                uid: 103,
+            })
+            .await?;
+        match late.recv().await.expect("Hub is dead") {
+            WsMessage::LobbyState { seeks, games } => {
+                assert!(seeks.is_empty());
+                assert_eq!(games.len(), 1);
+            }
+            msg => panic!("Expected lobby state, got {:?}", msg),
+        }
+
+        handle.send(Message::Resign { game_id, uid: 100 }).await?;
+        let msg = lobby.recv().await.expect("Hub is dead");
+        assert!(matches!(
+            msg,
+            WsMessage::GameEnded { game_id: ended } if ended == game_id
+        ));
+
+        Ok(())
+    }
+
+    // A new Ws connection of uid, returns its Ws Tx and Rx
+    async fn connect(
+        handle: &Handle,
+        uid: IdType,
+    ) -> Result<(mpsc::Sender<WsMessage>, mpsc::Receiver<WsMessage>), Box<dyn std::error::Error>>
+    {
+        let (respond_to, receiver) = mpsc::channel::<WsMessage>(8);
+        let msg = Message::WsConnect {
+            respond_to: respond_to.clone(),
+            uid,
+        };
+        handle.send(msg).await?;
+        Ok((respond_to, receiver))
+    }
+
+    async fn challenge(
+        handle: &Handle,
+        msg: GamePreference,
+        [from, to]: [IdType; 2],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (respond_to, _) = mpsc::channel::<WsMessage>(8);
+        let msg = Message::Challenge {
+            to_user: to,
+            msg,
+            respond_to,
+            uid: from,
+            name: format!("user {}", from),
+        };
+        handle.send(msg).await?;
+        Ok(())
+    }
+
+    async fn recv_challenge(receiver: &mut mpsc::Receiver<WsMessage>) -> ChallengeInfo {
+        match receiver.recv().await.expect("Hub is dead") {
+            WsMessage::ChallengeOffer(info) => info,
+            msg => panic!("Expected challenge offer, got {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn chess_hub_challenge() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let (_, mut challenger) = connect(&handle, 100).await?;
+
+        // Stored until the challenged user connects
+        challenge(&handle, seek(ColorPreference::Black, 0), [100, 101]).await?;
+        let info = recv_challenge(&mut challenger).await;
+        assert_eq!((info.from, info.to), (100, 101));
+        let (tx, mut challenged) = connect(&handle, 101).await?;
+        assert_eq!(recv_challenge(&mut challenged).await.id, info.id);
+
+        // Only the challenged user accepts
+        let (respond_to, mut other) = mpsc::channel::<WsMessage>(8);
+        let name = String::from("user 102");
+        let id = info.id;
+        let msg = Message::ChallengeAccept {
+            id,
+            respond_to,
+            uid: 102,
+            name,
+        };
+        handle.send(msg).await?;
+        let msg = other.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        let name = String::from("user 101");
+        let msg = Message::ChallengeAccept {
+            id,
+            respond_to: tx,
+            uid: 101,
+            name,
+        };
+        handle.send(msg).await?;
+        assert_eq!(recv_color(&mut challenger).await, WsColor::Black);
+        assert_eq!(recv_color(&mut challenged).await, WsColor::White);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_challenge_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let config = HubConfig {
+            challenge_limit: 1,
+            ..Default::default()
+        };
+        let handle = Handle::new(None, config);
+        let (tx, mut challenger) = connect(&handle, 100).await?;
+        let (respond_to, mut challenged) = connect(&handle, 101).await?;
+
+        challenge(&handle, GamePreference::default(), [100, 101]).await?;
+        let id = recv_challenge(&mut challenger).await.id;
+        recv_challenge(&mut challenged).await;
+        let msg = Message::Challenge {
+            to_user: 102,
+            msg: GamePreference::default(),
+            respond_to: tx.clone(),
+            uid: 100,
+            name: String::from("user 100"),
+        };
+        handle.send(msg).await?;
+        let msg = challenger.recv().await.expect("Hub is dead");
+        assert!(
+            matches!(msg, WsMessage::CommandRejected { reason } if reason == "too many pending challenges")
+        );
+
+        // The challenger must be online to start the game
+        let uid = 100;
+        handle
+            .send(Message::WsDisconnect {
+                respond_to: tx,
+                uid,
+            })
+            .await?;
+        let name = String::from("user 101");
+        let msg = Message::ChallengeAccept {
+            id,
+            respond_to,
+            uid: 101,
+            name,
+        };
+        handle.send(msg).await?;
+        let msg = challenged.recv().await.expect("Hub is dead");
+        assert!(
+            matches!(msg, WsMessage::CommandRejected { reason } if reason == "challenger is offline")
+        );
+        assert_members(&handle).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_challenge_unknown_user() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(Some(init_db().await?), HubConfig::default());
+        let (respond_to, mut challenger) = mpsc::channel::<WsMessage>(8);
+        let msg = Message::Challenge {
+            to_user: IdType::MAX,
+            msg: GamePreference::default(),
+            respond_to,
+            uid: 100,
+            name: String::from("user 100"),
+        };
+        handle.send(msg).await?;
+        let msg = challenger.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { reason } if reason == "no such user"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_challenge_decline() -> Result<(), Box<dyn std::error::Error>> {
+        let config = HubConfig {
+            challenge_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let handle = Handle::new(None, config);
+        let (_, mut challenger) = connect(&handle, 100).await?;
+        let (_, mut challenged) = connect(&handle, 101).await?;
+
+        challenge(&handle, GamePreference::default(), [100, 101]).await?;
+        let id = recv_challenge(&mut challenger).await.id;
+        recv_challenge(&mut challenged).await;
+        handle
+            .send(Message::ChallengeDecline { id, uid: 101 })
+            .await?;
+        for rx in [&mut challenger, &mut challenged] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::ChallengeDecline { id: declined } if declined == id));
+        }
+
+        challenge(&handle, GamePreference::default(), [100, 101]).await?;
+        let id = recv_challenge(&mut challenger).await.id;
+        recv_challenge(&mut challenged).await;
+        handle
+            .send(Message::ChallengeCancel { id, uid: 101 })
+            .await?; // not the challenger
+        handle
+            .send(Message::ChallengeCancel { id, uid: 100 })
+            .await?;
+        for rx in [&mut challenger, &mut challenged] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::ChallengeCancel { .. }));
+        }
+
+        challenge(&handle, GamePreference::default(), [100, 101]).await?;
+        recv_challenge(&mut challenger).await;
+        recv_challenge(&mut challenged).await;
+        for rx in [&mut challenger, &mut challenged] {
+            let msg = rx.recv().await.expect("Hub is dead");
+            assert!(matches!(msg, WsMessage::ChallengeExpired { .. }));
+        }
+
+        Ok(())
+    }
+
+    async fn chat(
+        handle: &Handle,
+        room: ChatRoom,
+        text: &str,
+        uid: IdType,
+    ) -> Result<mpsc::Receiver<WsMessage>, Box<dyn std::error::Error>> {
+        let (respond_to, receiver) = mpsc::channel::<WsMessage>(8);
+        let msg = Message::Chat {
+            room,
+            text: String::from(text),
+            respond_to,
+            uid,
+            name: format!("user {}", uid),
+        };
+        handle.send(msg).await?;
+        Ok(receiver)
+    }
+
+    #[tokio::test]
+    async fn chess_hub_chat() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let config = HubConfig {
+            chat_limit: 2,
+            chat_max_len: 10,
+            ..Default::default()
+        };
+        let handle = Handle::new(Some(db.clone()), config);
+        let msg = GamePreference::default();
+        let (mut white, mut black) = start_game(&handle, msg, [100, 101]).await?;
+        let game_id = white.2;
+        let (respond_to, mut spectator) = mpsc::channel::<WsMessage>(8);
+        let msg = Message::Spectate {
+            game_id,
+            respond_to,
+            uid: 102,
+        };
+        handle.send(msg).await?;
+        spectator.recv().await.expect("Hub is dead");
+        for rx in [&mut white.1, &mut black.1] {
+            rx.recv().await.expect("Hub is dead");
+        }
+
+        // Players talk among themselves, spectators in their own room
+        chat(&handle, ChatRoom::Game(game_id), "hi", white.0).await?;
+        match black.1.recv().await.expect("Hub is dead") {
+            WsMessage::ChatLine(line) => {
+                assert_eq!((line.uid, line.text.as_str()), (white.0, "hi"));
+                assert_eq!(line.room, ChatRoom::Game(game_id));
+            }
+            msg => panic!("Expected chat line, got {:?}", msg),
+        }
+        let mut rx = chat(&handle, ChatRoom::Spectators(game_id), "nice", white.0).await?;
+        let msg = rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+        let mut rx = chat(&handle, ChatRoom::Game(game_id), "far too long", black.0).await?;
+        let msg = rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::CommandRejected { .. }));
+
+        // Muted lines don't arrive
+        let (uid, muted) = (black.0, white.0);
+        handle.send(Message::Mute { uid, muted }).await?;
+        chat(&handle, ChatRoom::Game(game_id), "hello?", white.0).await?;
+        let mut rx = chat(&handle, ChatRoom::Game(game_id), "anyone?", white.0).await?;
+        let msg = rx.recv().await.expect("Hub is dead");
+        assert!(
+            matches!(msg, WsMessage::CommandRejected { .. }),
+            "rate limited"
+        );
+        handle.send(Message::Unmute { uid, muted }).await?;
+        chat(&handle, ChatRoom::Game(game_id), "gg", black.0).await?;
+        let msg = white.1.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::ChatLine(line) if line.text == "gg"));
+
+        // The chat is stored with the game
+        handle
+            .send(Message::Resign {
+                game_id,
+                uid: white.0,
+            })
+            .await?;
+        for rx in [&mut white.1, &mut black.1] {
+            recv_game_end(rx).await;
+        }
+        let tag = format!("[GameId \"{}\"]", game_id);
+        for _ in 0..50 {
+            let games = GameMac::list(&db).await?;
+            let game = games.iter().find(|game| game.pgn.contains(&tag));
+            let lines = match game {
+                Some(game) => ChatMac::of_game(&db, game.id).await?,
+                None => vec![],
+            };
+            if lines.len() == 3 {
+                assert_eq!(lines[2].text, "gg");
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("chat not stored");
+    }
+
+    #[tokio::test]
+    async fn chess_hub_mute_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let config = HubConfig {
+            mute_limit: 1,
+            ..Default::default()
+        };
+        let handle = Handle::new(None, config);
+        let mut lobby = vec![];
+        for uid in [100, 101, 102] {
+            let (respond_to, mut rx) = mpsc::channel::<WsMessage>(8);
+            handle.send(Message::Lobby { respond_to, uid }).await?;
+            rx.recv().await.expect("Hub is dead");
+            lobby.push(rx);
+        }
+
+        // Only the first mute is kept
+        for muted in [101, 102] {
+            handle.send(Message::Mute { uid: 100, muted }).await?;
+        }
+        chat(&handle, ChatRoom::Lobby, "muted", 101).await?;
+        chat(&handle, ChatRoom::Lobby, "heard", 102).await?;
+        let msg = lobby[0].recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::ChatLine(line) if line.text == "heard"));
+
+        Ok(())
+    }
+
+    fn correspondence(days: u32) -> GamePreference {
+        GamePreference {
+            tc: TimeControl {
+                days,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn chess_hub_correspondence_days() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Handle::new(None, HubConfig::default());
+        let mut rx = request(&handle, correspondence(MAX_DAYS + 1), 100).await?;
+        let msg = rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::GameRequestRejected { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chess_hub_correspondence_restart() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let handle = Handle::new(Some(db.clone()), HubConfig::default());
+        let uids = [random_uid(), random_uid()];
+        let (mut white, mut black) = start_game(&handle, correspondence(3), uids).await?;
+        let game_id = white.2;
+        make_moves(&handle, &mut white, &mut black, &["e2e4"]).await?;
+        assert_members(&handle).await;
+        let stored = poll_stored(&db, game_id, |g| g.is_some_and(|g| g.moves == "e2e4")).await?;
+
+        // The server restarts, Black comes back and is shown the move
+        drop(handle);
+        let handle = Handle::new(Some(db.clone()), HubConfig::default());
+        let games = stored.into_iter().collect();
+        handle
+            .send(Message::RestoreCorrespondence { games })
+            .await?;
+        let (respond_to, mut black_rx) = mpsc::channel::<WsMessage>(8);
+        let uid = black.0;
+        handle.send(Message::WsConnect { respond_to, uid }).await?;
+        match black_rx.recv().await.expect("Hub is dead") {
+            WsMessage::GameState(state) => {
+                assert_eq!(state.game_id, game_id);
+                assert_eq!(state.moves, vec![String::from("e2e4")]);
+                assert_eq!(state.color, WsColor::Black);
+                assert_eq!(state.opponent_name, format!("user {}", white.0));
+                let due = state.deadline.expect("correspondence deadline");
+                assert!(due > Utc::now().timestamp() + 2 * 86400);
+            }
+            msg => panic!("Expected game state, got {:?}", msg),
+        }
+        assert_members(&handle).await;
+
+        // The game goes on and its end removes it from the Db
+        let (respond_to, mut white_rx) = mpsc::channel::<WsMessage>(8);
+        let uid = white.0;
+        handle.send(Message::WsConnect { respond_to, uid }).await?;
+        white_rx.recv().await.expect("Hub is dead");
+        let uci = String::from("e7e5");
+        handle
+            .send(Message::Move {
+                game_id,
+                uci,
+                uid: black.0,
+            })
+            .await?;
+        let msg = white_rx.recv().await.expect("Hub is dead");
+        assert!(matches!(msg, WsMessage::Move { uci, .. } if uci == "e7e5"));
+        handle.send(Message::Resign { game_id, uid }).await?;
+        recv_game_end(&mut white_rx).await;
+        assert_members(&handle).await;
+        poll_stored(&db, game_id, |g| g.is_none()).await?;
+
+        Ok(())
+    }
+
+    // Correspondence rows are written off the Hub loop, the row of game_id once `until` it
+    async fn poll_stored(
+        db: &Db,
+        game_id: LiveGameId,
+        until: impl Fn(Option<&correspondence::Model>) -> bool,
+    ) -> Result<Option<correspondence::Model>, Box<dyn std::error::Error>> {
+        for _ in 0..50 {
+            let stored = CorrespondenceMac::list(db).await?;
+            let stored = stored.into_iter().find(|g| g.id == game_id.to_string());
+            if until(stored.as_ref()) {
+                return Ok(stored);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("correspondence game {} not stored as expected", game_id);
+    }
+
+    #[tokio::test]
+    async fn chess_hub_correspondence_deadline() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let game_id = LiveGameId::new_v4();
+        let (white, black) = (random_uid(), random_uid());
+        let stored = correspondence::Model {
+            id: game_id.to_string(),
+            white,
+            black,
+            white_name: format!("user {}", white),
+            black_name: format!("user {}", black),
+            days: 1,
+            rated: false,
+            takebacks: true,
+            date: String::from("2023-04-17"),
+            moves: String::from("e2e4 e7e5"),
+            deadline: Utc::now().timestamp() - 60,
+        };
+        CorrespondenceMac::save(&db, stored.clone()).await?;
+
+        // White's move was due while the server was down
+        let handle = Handle::new(Some(db.clone()), HubConfig::default());
+        let games = vec![stored];
+        handle
+            .send(Message::RestoreCorrespondence { games })
+            .await?;
+        let tag = format!("[GameId \"{}\"]", game_id);
+        for _ in 0..50 {
+            let games = GameMac::list(&db).await?;
+            if let Some(game) = games.iter().find(|game| game.pgn.contains(&tag)) {
+                assert!(game.pgn.contains("[Result \"0-1\"]"));
+                assert!(game.pgn.contains("[Termination \"time forfeit\"]"));
+                assert!(game.pgn.contains("1. e4 e5 0-1"));
+                poll_stored(&db, game_id, |g| g.is_none()).await?;
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("correspondence game not ended");
     }
 }
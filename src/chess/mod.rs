@@ -1,9 +1,16 @@
+pub mod clock;
+pub mod engine;
+pub mod glicko;
 pub mod hub;
+pub mod pgn;
 pub mod uci;
 
 use serde::{Deserialize, Serialize};
+use shakmaty::{Color, Outcome};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 enum ColorPreference {
     #[default]
     Any,
@@ -11,23 +18,284 @@ enum ColorPreference {
     Black,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-enum OpponentPreference {
+impl ColorPreference {
+    /// My color against an opponent's preference, None if both want the same color.
+    fn against(self, other: ColorPreference) -> Option<Color> {
+        use ColorPreference::*;
+        match (self, other) {
+            (White, White) | (Black, Black) => None,
+            (White, _) | (Any, Black) => Some(Color::White),
+            (Black, _) | (Any, White) => Some(Color::Black),
+            (Any, Any) => Some(Color::from_white(rand::random())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub enum OpponentPreference {
     #[default]
     Human,
-    Lc0,
-    Sockfish,
+    Engine(String), // name of a configured engine
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct TimeControl {
+/// Longest main time of a game, in seconds
+pub const MAX_MAIN: u32 = 3 * 3600;
+/// Longest increment, in seconds
+pub const MAX_INCR: u32 = 180;
+/// Longest time per move of a correspondence game
+pub const MAX_DAYS: u32 = 14;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
     main: u32, // main game time in seconds
     incr: u32,
+    #[serde(default)]
+    days: u32, // days per move, a correspondence game if non-zero
+}
+
+impl TimeControl {
+    /// Close enough to pair: main time within 10%, increment within a second,
+    /// correspondence games only with the same days per move.
+    fn matches(&self, other: &TimeControl) -> bool {
+        if self.days > 0 || other.days > 0 {
+            return self.days == other.days;
+        }
+        let main_tolerance = self.main.max(other.main) / 10;
+        self.main.abs_diff(other.main) <= main_tolerance && self.incr.abs_diff(other.incr) <= 1
+    }
+
+    /// Days per move of a correspondence game, None for a game played in one sitting.
+    pub fn days(&self) -> Option<u32> {
+        Some(self.days).filter(|days| *days > 0)
+    }
+
+    /// Err for a time control the Hub can't play
+    pub fn validate(&self) -> Result<(), String> {
+        if self.main > MAX_MAIN {
+            return Err(format!("at most {} seconds main time", MAX_MAIN));
+        }
+        if self.incr > MAX_INCR {
+            return Err(format!("at most {} seconds increment", MAX_INCR));
+        }
+        if self.days > MAX_DAYS {
+            return Err(format!("at most {} days per move", MAX_DAYS));
+        }
+        Ok(())
+    }
+
+    /// Rating category by estimated game duration (main + 40 increments),
+    /// None for untimed games which are not rated.
+    pub fn category(&self) -> Option<TimeCategory> {
+        if self.days > 0 {
+            return Some(TimeCategory::Correspondence);
+        }
+        let incr = u64::from(self.incr).saturating_mul(40);
+        let estimated = u64::from(self.main).saturating_add(incr);
+        match self.main {
+            0 => None,
+            _ if estimated < 180 => Some(TimeCategory::Bullet),
+            _ if estimated < 480 => Some(TimeCategory::Blitz),
+            _ if estimated < 1500 => Some(TimeCategory::Rapid),
+            _ => Some(TimeCategory::Classical),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeCategory {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl TimeCategory {
+    /// Db representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeCategory::Bullet => "bullet",
+            TimeCategory::Blitz => "blitz",
+            TimeCategory::Rapid => "rapid",
+            TimeCategory::Classical => "classical",
+            TimeCategory::Correspondence => "correspondence",
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+impl FromStr for TimeCategory {
+    type Err = String;
+
+    fn from_str(category: &str) -> Result<Self, Self::Err> {
+        [
+            TimeCategory::Bullet,
+            TimeCategory::Blitz,
+            TimeCategory::Rapid,
+            TimeCategory::Classical,
+            TimeCategory::Correspondence,
+        ]
+        .into_iter()
+        .find(|c| c.as_str() == category)
+        .ok_or_else(|| format!("unknown time category {}", category))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct GamePreference {
     color: ColorPreference,
     tc: TimeControl,
     opponent: OpponentPreference,
+    #[serde(default)]
+    level: Option<u8>, // engine strength, full strength if None
+    #[serde(default)]
+    disable_takebacks: bool, // e.g. for rated games, both sides must allow them
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Aborted, // no result
+}
+
+impl GameResult {
+    pub fn win(winner: Color) -> Self {
+        match winner {
+            Color::White => GameResult::WhiteWins,
+            Color::Black => GameResult::BlackWins,
+        }
+    }
+
+    /// Rating score of White, None if the game has no result
+    pub fn white_score(&self) -> Option<f64> {
+        match self {
+            GameResult::WhiteWins => Some(1.0),
+            GameResult::BlackWins => Some(0.0),
+            GameResult::Draw => Some(0.5),
+            GameResult::Aborted => None,
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Aborted => "*",
+        })
+    }
+}
+
+impl From<Outcome> for GameResult {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Decisive { winner } => GameResult::win(winner),
+            Outcome::Draw => GameResult::Draw,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameEndReason {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    Timeout,
+    Abandoned,
+    Aborted,
+    Resignation,
+    DrawAgreement,
+}
+
+impl GameEndReason {
+    /// PGN Termination tag value
+    pub fn termination(&self) -> &'static str {
+        match self {
+            GameEndReason::Timeout => "time forfeit",
+            GameEndReason::Abandoned | GameEndReason::Aborted => "abandoned",
+            _ => "normal",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chess_color_preference() {
+        use ColorPreference::*;
+        assert_eq!(White.against(Any), Some(Color::White));
+        assert_eq!(Any.against(White), Some(Color::Black));
+        assert_eq!(Black.against(White), Some(Color::Black));
+        assert_eq!(Any.against(Black), Some(Color::White));
+        assert_eq!(White.against(White), None);
+        assert_eq!(Black.against(Black), None);
+        assert!(Any.against(Any).is_some());
+    }
+
+    #[test]
+    fn chess_time_control_matches() {
+        let tc = |main, incr| TimeControl {
+            main,
+            incr,
+            days: 0,
+        };
+        assert!(tc(300, 3).matches(&tc(300, 3)));
+        assert!(tc(300, 3).matches(&tc(280, 2)));
+        assert!(!tc(300, 3).matches(&tc(180, 3)));
+        assert!(!tc(300, 3).matches(&tc(300, 5)));
+        assert!(tc(0, 0).matches(&tc(0, 0)));
+        assert!(!tc(0, 0).matches(&tc(60, 0)));
+
+        let days = |days| TimeControl { days, ..tc(0, 0) };
+        assert!(days(3).matches(&days(3)));
+        assert!(!days(3).matches(&days(1)));
+        assert!(!days(3).matches(&tc(0, 0)));
+        assert!(days(MAX_DAYS).validate().is_ok());
+        assert!(days(MAX_DAYS + 1).validate().is_err());
+        assert!(days(u32::MAX).validate().is_err());
+
+        assert!(tc(MAX_MAIN, MAX_INCR).validate().is_ok());
+        assert!(tc(u32::MAX, 0).validate().is_err());
+        assert!(tc(0, u32::MAX).validate().is_err());
+        assert!(tc(u32::MAX, 0).matches(&tc(u32::MAX - 1, 0)));
+        assert!(!tc(0, u32::MAX).matches(&tc(0, 0)));
+    }
+
+    #[test]
+    fn chess_time_category() {
+        let tc = |main, incr| TimeControl {
+            main,
+            incr,
+            days: 0,
+        };
+        assert_eq!(tc(0, 0).category(), None);
+        assert_eq!(tc(60, 1).category(), Some(TimeCategory::Bullet));
+        assert_eq!(tc(180, 0).category(), Some(TimeCategory::Blitz));
+        assert_eq!(tc(180, 2).category(), Some(TimeCategory::Blitz));
+        assert_eq!(tc(600, 5).category(), Some(TimeCategory::Rapid));
+        assert_eq!(tc(1800, 0).category(), Some(TimeCategory::Classical));
+        assert_eq!(tc(u32::MAX, 0).category(), Some(TimeCategory::Classical));
+        assert_eq!(tc(1, u32::MAX).category(), Some(TimeCategory::Classical));
+        assert_eq!(
+            tc(u32::MAX, u32::MAX).category(),
+            Some(TimeCategory::Classical)
+        );
+        let tc = TimeControl {
+            days: 3,
+            ..tc(0, 0)
+        };
+        assert_eq!(tc.category(), Some(TimeCategory::Correspondence));
+        assert_eq!(tc.days(), Some(3));
+
+        let category = TimeCategory::Rapid;
+        assert_eq!(category.as_str().parse(), Ok(category));
+        assert!("blitzkrieg".parse::<TimeCategory>().is_err());
+    }
 }
@@ -0,0 +1,201 @@
+use chrono::NaiveDate;
+use shakmaty::{san::SanPlus, Chess, Move};
+use std::fmt::Write;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{GameEndReason, GameResult, TimeControl};
+
+const PGN_LINE_WIDTH: usize = 79;
+
+/// A move and the mover's remaining time after it, if the game is timed.
+#[derive(Debug, Clone)]
+pub struct PlayedMove {
+    pub m: Move,
+    pub clock: Option<Duration>,
+}
+
+/// A finished game, exported as PGN.
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    pub white: String,
+    pub black: String,
+    pub date: NaiveDate,
+    pub tc: TimeControl,
+    pub rated: bool,
+    pub result: GameResult,
+    pub reason: GameEndReason,
+    pub moves: Vec<PlayedMove>,
+    pub game_id: Option<Uuid>, // the live game
+}
+
+fn clk(clock: Duration) -> String {
+    let secs = clock.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl PgnGame {
+    fn event(&self) -> &'static str {
+        match self.rated {
+            true => "Rated game",
+            false => "Casual game",
+        }
+    }
+
+    fn time_control_tag(&self) -> String {
+        if let Some(days) = self.tc.days() {
+            // one move per period, in seconds
+            return format!("1/{}", days * 86400);
+        }
+        match self.tc.main {
+            0 => String::from("-"),
+            main => format!("{}+{}", main, self.tc.incr),
+        }
+    }
+
+    fn movetext_tokens(&self) -> Vec<String> {
+        let mut pos = Chess::default();
+        let mut tokens = vec![];
+        for (ply, played) in self.moves.iter().enumerate() {
+            if ply % 2 == 0 {
+                tokens.push(format!("{}.", ply / 2 + 1));
+            }
+            let san = SanPlus::from_move_and_play_unchecked(&mut pos, &played.m);
+            tokens.push(san.to_string());
+            if let Some(clock) = played.clock {
+                tokens.push(format!("{{ [%clk {}] }}", clk(clock)));
+            }
+        }
+        tokens.push(self.result.to_string());
+        tokens
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let tags = [
+            ("Event", String::from(self.event())),
+            ("Site", String::from("sheled")),
+            ("Date", self.date.format("%Y.%m.%d").to_string()),
+            ("Round", String::from("-")),
+            ("White", escape(&self.white)),
+            ("Black", escape(&self.black)),
+            ("Result", self.result.to_string()),
+            ("TimeControl", self.time_control_tag()),
+            ("Termination", self.reason.termination().to_owned()),
+        ];
+        for (name, value) in tags {
+            let _ = writeln!(pgn, "[{} \"{}\"]", name, value);
+        }
+        if let Some(game_id) = self.game_id {
+            let _ = writeln!(pgn, "[GameId \"{}\"]", game_id);
+        }
+        pgn.push('\n');
+
+        // movetext lines are kept below 80 characters
+        let mut line = String::new();
+        for token in self.movetext_tokens() {
+            if !line.is_empty() && line.len() + 1 + token.len() > PGN_LINE_WIDTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+        pgn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::{uci::Uci, Position};
+    use std::str::FromStr;
+
+    fn played_moves(ucis: &[&str], clock: Option<Duration>) -> Vec<PlayedMove> {
+        let mut pos = Chess::default();
+        ucis.iter()
+            .map(|uci| {
+                let m = Uci::from_str(uci).unwrap().to_move(&pos).unwrap();
+                pos.play_unchecked(&m);
+                PlayedMove { m, clock }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chess_pgn_export() {
+        let game = PgnGame {
+            white: String::from("Some \"One\""),
+            black: String::from("Other"),
+            date: NaiveDate::from_ymd_opt(2023, 4, 17).unwrap(),
+            tc: TimeControl {
+                main: 300,
+                incr: 3,
+                days: 0,
+            },
+            rated: false,
+            result: GameResult::BlackWins,
+            reason: GameEndReason::Checkmate,
+            moves: played_moves(
+                &["f2f3", "e7e5", "g2g4", "d8h4"],
+                Some(Duration::from_secs(3725)),
+            ),
+            game_id: Some(Uuid::nil()),
+        };
+        let pgn = game.to_pgn();
+        println!("{}", pgn);
+
+        assert!(pgn.starts_with("[Event \"Casual game\"]\n"));
+        assert!(pgn.contains("[Date \"2023.04.17\"]\n"));
+        assert!(pgn.contains("[White \"Some \\\"One\\\"\"]\n"));
+        assert!(pgn.contains("[Result \"0-1\"]\n"));
+        assert!(pgn.contains("[TimeControl \"300+3\"]\n"));
+        assert!(pgn.contains("[GameId \"00000000-0000-0000-0000-000000000000\"]\n"));
+        assert!(pgn.contains("1. f3 { [%clk 1:02:05] } e5 { [%clk 1:02:05] }"));
+        assert!(pgn.ends_with(" Qh4#\n{ [%clk 1:02:05] } 0-1\n"));
+        assert!(pgn.lines().all(|line| line.len() < 80));
+
+        let pgn = PgnGame {
+            rated: true,
+            ..game
+        }
+        .to_pgn();
+        assert!(pgn.starts_with("[Event \"Rated game\"]\n"));
+    }
+
+    #[test]
+    fn chess_pgn_export_untimed() {
+        let game = PgnGame {
+            white: String::from("?"),
+            black: String::from("?"),
+            date: NaiveDate::from_ymd_opt(2023, 4, 17).unwrap(),
+            tc: TimeControl::default(),
+            rated: false,
+            result: GameResult::Draw,
+            reason: GameEndReason::ThreefoldRepetition,
+            moves: played_moves(&["g1f3", "g8f6", "f3g1", "f6g8"].repeat(2), None),
+            game_id: None,
+        };
+        let pgn = game.to_pgn();
+        println!("{}", pgn);
+
+        assert!(pgn.contains("[TimeControl \"-\"]\n"));
+        assert!(pgn.ends_with("\n\n1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 1/2-1/2\n"));
+
+        let tc = TimeControl {
+            days: 3,
+            ..TimeControl::default()
+        };
+        let pgn = PgnGame { tc, ..game }.to_pgn();
+        assert!(pgn.contains("[TimeControl \"1/259200\"]\n"));
+    }
+}
@@ -0,0 +1,23 @@
+#!/bin/sh
+# Scripted stand-in UCI engine for tests, plays its arguments in order,
+# a "-" thinks forever on that move
+while read -r cmd rest; do
+    case "$cmd" in
+    uci)
+        echo "id name fake_uci"
+        echo "uciok"
+        ;;
+    isready) echo "readyok" ;;
+    go)
+        if [ "$1" = "-" ]; then
+            shift
+        elif [ $# -gt 0 ]; then
+            echo "bestmove $1"
+            shift
+        else
+            echo "bestmove (none)"
+        fi
+        ;;
+    quit) exit 0 ;;
+    esac
+done
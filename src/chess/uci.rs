@@ -12,11 +12,11 @@ pub enum Error {
 }
 
 pub trait UciMove {
-    fn make_move(&mut self, new_move: &str) -> Result<(), Error>;
+    fn make_move(&mut self, new_move: &str) -> Result<Move, Error>;
 }
 
 impl UciMove for Chess {
-    fn make_move(&mut self, new_move: &str) -> Result<(), Error> {
+    fn make_move(&mut self, new_move: &str) -> Result<Move, Error> {
         let new_move = Uci::from_str(new_move)?.to_move(self)?;
 
         // illegal moves are filtered out by
@@ -24,7 +24,7 @@ impl UciMove for Chess {
         assert!(self.is_legal(&new_move));
 
         self.play_unchecked(&new_move);
-        Ok(())
+        Ok(new_move)
     }
 }
 
@@ -52,26 +52,17 @@ mod tests {
 
         let res = game.make_move("e2e5");
         assert!(res.is_err());
-        assert!(match res {
-            Err(Error::IllegalUci(_)) => true,
-            _ => false,
-        });
+        assert!(matches!(res, Err(Error::IllegalUci(_))));
         println!("res {:?}", res);
 
         let res = game.make_move("wtf?");
         assert!(res.is_err());
-        assert!(match res {
-            Err(Error::ParseUci(_)) => true,
-            _ => false,
-        });
+        assert!(matches!(res, Err(Error::ParseUci(_))));
         println!("res {:?}", res);
 
         let res = game.make_move("e7d4");
         assert!(res.is_err());
-        assert!(match res {
-            Err(Error::IllegalUci(_)) => true,
-            _ => false,
-        });
+        assert!(matches!(res, Err(Error::IllegalUci(_))));
         println!("res {:?}", res);
 
         assert!(game.make_move("e7e5").is_ok());
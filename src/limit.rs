@@ -0,0 +1,253 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::HeaderMap;
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+// Keep at most this many buckets, refuse new keys while none is full again
+const MAX_BUCKETS: usize = 100_000;
+
+/// Rejection of a request over its rate, to retry after some seconds.
+#[derive(Debug)]
+pub struct TooManyRequests {
+    pub retry_after: u64,
+}
+
+impl Reject for TooManyRequests {}
+
+impl TooManyRequests {
+    pub fn after(wait: Duration) -> Self {
+        TooManyRequests {
+            retry_after: wait.as_secs_f64().ceil().max(1.0) as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+// Buckets by key, and their keys by when they are full again. A full bucket
+// is as good as none, forgetting it resets no limit.
+#[derive(Debug, Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    by_full: BTreeSet<(Instant, String)>,
+}
+
+/// Token buckets by key, each holding up to `capacity` requests and
+/// regaining `per_sec` of them a second.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    capacity: f64,
+    per_sec: f64,
+    max_buckets: usize,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, per_sec: f64) -> Self {
+        RateLimiter {
+            capacity: capacity as f64,
+            per_sec,
+            max_buckets: MAX_BUCKETS,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a request from the bucket of `key`, or tells how long until there is one.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    // When `bucket` holds `capacity` tokens again
+    fn full_at(&self, bucket: &Bucket) -> Instant {
+        let missing = self.capacity - bucket.tokens;
+        bucket.at + Duration::from_secs_f64(missing / self.per_sec)
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { by_key, by_full } = &mut *buckets;
+        if !by_key.contains_key(key) && by_key.len() >= self.max_buckets {
+            // limited keys stay, a flood of new ones can't reset them
+            match by_full.pop_first() {
+                Some((full, soonest)) if full <= now => {
+                    by_key.remove(&soonest);
+                }
+                Some((full, soonest)) => {
+                    by_full.insert((full, soonest));
+                    return Err(full - now);
+                }
+                None => (),
+            }
+        }
+        let bucket = by_key.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.capacity,
+            at: now,
+        });
+        by_full.remove(&(self.full_at(bucket), key.to_owned()));
+        let regained = now.duration_since(bucket.at).as_secs_f64() * self.per_sec;
+        bucket.tokens = (bucket.tokens + regained).min(self.capacity);
+        bucket.at = now;
+        let taken = match bucket.tokens < 1.0 {
+            true => Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_sec,
+            )),
+            false => {
+                bucket.tokens -= 1.0;
+                Ok(())
+            }
+        };
+        by_full.insert((self.full_at(bucket), key.to_owned()));
+
+        taken
+    }
+}
+
+// An address as a proxy header gives it, e.g. `10.0.0.1`, `"[::1]:4711"`
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse::<SocketAddr>()
+        .map(|a| a.ip())
+        .or_else(|_| value.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+/// The client's IP address, as the last hop appended to the `proxy` header
+/// (`Forwarded` or the like of `X-Forwarded-For`) by a trusted reverse proxy,
+/// else as connected. Earlier hops are the client's to make up.
+fn client_ip(addr: Option<SocketAddr>, headers: &HeaderMap, proxy: Option<&str>) -> String {
+    let forwarded = proxy
+        .and_then(|name| Some((name, headers.get_all(name).iter().next_back()?)))
+        .and_then(|(name, value)| {
+            let hop = value.to_str().ok()?.rsplit(',').next()?;
+            match name.eq_ignore_ascii_case("forwarded") {
+                true => hop.split(';').find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    key.trim().eq_ignore_ascii_case("for").then_some(value)
+                }),
+                false => Some(hop),
+            }
+        })
+        .and_then(parse_ip);
+    forwarded
+        .or(addr.map(|a| a.ip()))
+        .map(|ip| ip.to_string())
+        .unwrap_or_default()
+}
+
+/// Filter - Rejects clients over the rate of `limiter`, by IP address,
+/// as told by the `proxy` header when behind a trusted reverse proxy
+pub fn with_rate_limit(
+    limiter: RateLimiter,
+    proxy: Option<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .and_then(move |addr: Option<SocketAddr>, headers: HeaderMap| {
+            let limiter = limiter.clone();
+            let ip = client_ip(addr, &headers, proxy.as_deref());
+            async move {
+                match limiter.check(&ip) {
+                    Ok(()) => Ok(()),
+                    Err(wait) => {
+                        println!("--> rate limited {}", ip);
+                        Err(warp::reject::custom(TooManyRequests::after(wait)))
+                    }
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_token_bucket() {
+        let limiter = RateLimiter::new(2, 0.5);
+        let start = Instant::now();
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("a", start).is_ok());
+        assert_eq!(limiter.check_at("a", start), Err(Duration::from_secs(2)));
+        assert!(limiter.check_at("b", start).is_ok(), "buckets are per key");
+
+        // Refills at its rate, never above capacity
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check_at("a", later), Err(Duration::from_secs(1)));
+        assert!(limiter
+            .check_at("a", later + Duration::from_secs(1))
+            .is_ok());
+        let much_later = start + Duration::from_secs(3600);
+        assert!(limiter.check_at("a", much_later).is_ok());
+        assert!(limiter.check_at("a", much_later).is_ok());
+        assert!(limiter.check_at("a", much_later).is_err());
+    }
+
+    #[test]
+    fn limit_evicts_full_buckets() {
+        let mut limiter = RateLimiter::new(2, 0.1);
+        limiter.max_buckets = 2;
+        let start = Instant::now();
+        let later = |secs| start + Duration::from_secs(secs);
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("b", later(5)).is_ok());
+
+        // No bucket is full again, new keys wait for the first that is
+        assert_eq!(limiter.check_at("c", later(6)), Err(Duration::from_secs(9)));
+        assert!(limiter.check_at("a", later(6)).is_err(), "still limited");
+        assert!(limiter.check_at("c", later(15)).is_ok(), "in place of b");
+        assert_eq!(limiter.buckets.lock().unwrap().by_key.len(), 2);
+        let b = limiter.check_at("b", later(16));
+        assert_eq!(b, Err(Duration::from_secs(4)), "forgotten, new again");
+    }
+
+    #[test]
+    fn limit_client_ip() {
+        let addr: Option<SocketAddr> = "10.0.0.1:5000".parse().ok();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4, 192.0.2.60".parse().unwrap());
+        let forwarded = "for=1.2.3.4, for=\"[2001:db8::1]:4711\";proto=https";
+        headers.insert("forwarded", forwarded.parse().unwrap());
+
+        assert_eq!(client_ip(addr, &headers, None), "10.0.0.1");
+        let ip = client_ip(addr, &headers, Some("X-Forwarded-For"));
+        assert_eq!(ip, "192.0.2.60");
+        assert_eq!(client_ip(addr, &headers, Some("Forwarded")), "2001:db8::1");
+        let ip = client_ip(addr, &headers, Some("X-Real-IP"));
+        assert_eq!(ip, "10.0.0.1", "without the header, as connected");
+    }
+
+    #[tokio::test]
+    async fn limit_filter_by_ip() {
+        let limited = with_rate_limit(RateLimiter::new(1, 0.01), None).map(warp::reply);
+        let addr: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let other: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        assert!(
+            warp::test::request()
+                .remote_addr(addr)
+                .matches(&limited)
+                .await
+        );
+        assert!(
+            !warp::test::request()
+                .remote_addr(addr)
+                .matches(&limited)
+                .await
+        );
+        assert!(
+            warp::test::request()
+                .remote_addr(other)
+                .matches(&limited)
+                .await
+        );
+    }
+}
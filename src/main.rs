@@ -1,20 +1,30 @@
 #![deny(warnings)]
 mod auth;
 mod chess;
+mod limit;
 mod model;
+mod rejection;
 mod ws;
 
+use std::path::Path;
 use warp::fs::dir;
 use warp::hyper::Uri;
 use warp::Filter;
 
-use auth::api::{login, signup};
-use auth::jwt::{current_key, MasterTokenSecret};
+use auth::api::{login, logout, refresh, signup};
+use auth::jwt::{load_keys, rotate_keys_periodically, MasterTokenSecret};
 use auth::{jwt, UserCtx};
-use chess::hub::Handle;
-use model::db::init_db;
+use chess::engine::load_engines;
+use chess::hub::{Handle, HubConfig};
+use limit::{with_rate_limit, RateLimiter};
+use model::db::{init_db, Db};
+use rejection::handle_rejection;
 use ws::user_connected;
 
+const ENGINES_CONFIG: &str = "engines.json";
+// Client address header of a trusted reverse proxy, e.g. X-Forwarded-For
+const TRUSTED_PROXY_HEADER: &str = "TRUSTED_PROXY_HEADER";
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Verbose logging
@@ -27,28 +37,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Filter/State - Extract Db connection
     let db = init_db().await?;
-    let jwt_secret = current_key(&db).await?; // Read currecnt JWT secret
+    let jwt_keys = load_keys(&db).await?; // Read the JWT signing keys
+    let mut hub_config = HubConfig::default();
+    match load_engines(Path::new(ENGINES_CONFIG)) {
+        Ok(engines) => hub_config.engines = engines,
+        Err(e) => eprintln!("No engines to play, {} error {}", ENGINES_CONFIG, e),
+    }
+    let hub = Handle::new(Some(db.clone()), hub_config);
+    if let Err(e) = hub.restore_correspondence(&db).await {
+        eprintln!("No correspondence games restored, error {:?}", e);
+    }
+    let db_rotation = db.clone();
     let db = warp::any().map(move || db.clone());
 
     // Filter/State - Extract JWT token secret
     let token_secret = MasterTokenSecret::default();
-    *token_secret.write().await = jwt_secret; // Init from Db
+    *token_secret.write().await = jwt_keys; // Init from Db
+    tokio::spawn(rotate_keys_periodically(db_rotation, token_secret.clone()));
     let token_secret = warp::any().map(move || token_secret.clone());
 
     // Filter - Accept only authenticated users
     // Extract the user context (utx) from JWT token cookie
+    // from a session which is still logged in
     let with_utx = warp::cookie::<String>("token")
         .and(token_secret.clone())
-        .and_then(
-            |token: String, token_secret| async move { jwt::to_utx(&token, token_secret).await },
-        );
+        .and(db.clone())
+        .and_then(|token: String, token_secret, db: Db| async move {
+            jwt::to_utx(&token, token_secret, &db).await
+        });
+
+    // Filter - Reject clients sending too many requests, by IP address
+    let proxy = std::env::var(TRUSTED_PROXY_HEADER).ok();
+    let rate_limit = with_rate_limit(RateLimiter::new(30, 1.0), proxy);
+
+    // Filter/State - Limit login attempts per account
+    let account_limit = RateLimiter::new(5, 1.0 / 60.0);
+    let account_limit = warp::any().map(move || account_limit.clone());
 
     // Filter/State - Extract Hub handle
-    let hub = Handle::new();
     let hub = warp::any().map(move || hub.clone());
 
     // /ws -> hub websocket interface
     let ws = warp::path("ws")
+        .and(rate_limit.clone())
         .and(with_utx.clone())
         .and(warp::ws())
         .and(db.clone())
@@ -71,24 +102,56 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // POST /login
     let login = warp::post()
         .and(warp::path("login"))
+        .and(rate_limit.clone())
         .and(token_secret.clone())
         .and(db.clone())
+        .and(account_limit)
         .and(warp::body::json())
-        .and_then(|token_secret, db, user| async move { login(token_secret, db, user).await });
+        .and_then(|token_secret, db, account_limit, user| async move {
+            login(token_secret, db, account_limit, user).await
+        });
 
     // POST /signup
     let signup = warp::post()
         .and(warp::path("signup"))
-        .and(token_secret)
+        .and(rate_limit.clone())
+        .and(token_secret.clone())
         .and(db.clone())
         .and(warp::body::json())
         .and_then(|token_secret, db, user| async move { signup(token_secret, db, user).await });
 
+    // POST /refresh - rotate the refresh token cookie, new access token
+    let refresh = warp::post()
+        .and(warp::path("refresh"))
+        .and(rate_limit.clone())
+        .and(token_secret)
+        .and(db.clone())
+        .and(warp::cookie::optional("refresh"))
+        .and_then(|token_secret, db, refresh_token| async move {
+            refresh(token_secret, db, refresh_token).await
+        });
+
+    // POST /logout - revoke the session
+    let logout = warp::post()
+        .and(warp::path("logout"))
+        .and(rate_limit)
+        .and(db.clone())
+        .and(warp::cookie::optional("refresh"))
+        .and_then(|db, refresh_token| async move { logout(db, refresh_token).await });
+
     // The default route - Log in with your account to continue.
     let redirect = warp::any().map(|| warp::redirect::temporary(Uri::from_static("/auth")));
 
+    // The JSON API and ws answer their errors with a status, the rest falls through
+    let api = login
+        .or(signup)
+        .or(refresh)
+        .or(logout)
+        .or(ws)
+        .recover(handle_rejection);
+
     // Compose all filters
-    let routes = auth.or(login).or(signup).or(ws).or(index).or(redirect);
+    let routes = auth.or(api).or(index).or(redirect);
     warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
 
     Ok(())
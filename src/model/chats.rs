@@ -0,0 +1,84 @@
+use super::db::Db;
+use crate::model;
+use sea_orm::entity::prelude::*;
+use sea_orm::*;
+
+// Chat lines of a stored game, in the order they were sent
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "chats")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: model::IdType,
+    #[sea_orm(indexed)]
+    pub game: model::IdType,
+    pub uid: model::IdType,
+    pub name: String,
+    pub room: String, // game or spectators
+    pub text: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct ChatMac;
+
+impl ChatMac {
+    pub async fn create(
+        db: &Db,
+        game: model::IdType,
+        uid: model::IdType,
+        name: &str,
+        room: &str,
+        text: &str,
+    ) -> Result<model::IdType, model::Error> {
+        let line = ActiveModel {
+            game: Set(game),
+            uid: Set(uid),
+            name: Set(name.to_owned()),
+            room: Set(room.to_owned()),
+            text: Set(text.to_owned()),
+            ..Default::default()
+        };
+        let res = Entity::insert(line).exec(db).await?;
+
+        Ok(res.last_insert_id)
+    }
+
+    #[cfg(test)]
+    pub async fn of_game(db: &Db, game: model::IdType) -> Result<Vec<Model>, model::Error> {
+        Ok(Entity::find()
+            .filter(Column::Game.eq(game))
+            .order_by_asc(Column::Id)
+            .all(db)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::db::init_db;
+
+    /*
+
+    cargo watch -q -c -w src -x 'test model_chat_ -- --nocapture --test-threads=1'
+
+     */
+    #[tokio::test]
+    async fn model_chat_of_game() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let game = rand::random::<u32>().into();
+
+        ChatMac::create(&db, game, 17, "Some One", "game", "hi").await?;
+        ChatMac::create(&db, game, 18, "Other", "spectators", "nice move").await?;
+
+        let lines = ChatMac::of_game(&db, game).await?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "hi");
+        assert_eq!(lines[1].room, "spectators");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use super::db::Db;
+use crate::model;
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::OnConflict;
+
+// Correspondence games in progress, rewritten after every move and removed at the end
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "correspondence")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String, // live game uuid
+    pub white: model::IdType,
+    pub black: model::IdType,
+    pub white_name: String,
+    pub black_name: String,
+    pub days: i32, // per move
+    pub rated: bool,
+    pub takebacks: bool,
+    pub date: String,  // game start, UTC %Y-%m-%d
+    pub moves: String, // uci, space separated
+    pub deadline: i64, // unix seconds, for the side to move
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct CorrespondenceMac;
+
+impl CorrespondenceMac {
+    pub async fn save(db: &Db, game: Model) -> Result<(), model::Error> {
+        let game: ActiveModel = game.into();
+        let on_conflict = OnConflict::column(Column::Id)
+            .update_columns([
+                Column::White,
+                Column::Black,
+                Column::WhiteName,
+                Column::BlackName,
+                Column::Days,
+                Column::Rated,
+                Column::Takebacks,
+                Column::Date,
+                Column::Moves,
+                Column::Deadline,
+            ])
+            .to_owned();
+        Entity::insert(game)
+            .on_conflict(on_conflict)
+            .exec_without_returning(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(db: &Db, id: &str) -> Result<(), model::Error> {
+        Entity::delete_by_id(id.to_owned()).exec(db).await?;
+
+        Ok(())
+    }
+
+    pub async fn list(db: &Db) -> Result<Vec<Model>, model::Error> {
+        Ok(Entity::find().all(db).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::db::init_db;
+
+    /*
+
+    cargo watch -q -c -w src -x 'test model_correspondence_ -- --nocapture --test-threads=1'
+
+     */
+    #[tokio::test]
+    async fn model_correspondence_save() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut game = Model {
+            id: id.clone(),
+            white: 17,
+            black: 18,
+            white_name: String::from("Some One"),
+            black_name: String::from("Other"),
+            days: 3,
+            rated: false,
+            takebacks: true,
+            date: String::from("2023-04-17"),
+            moves: String::new(),
+            deadline: 1681776000,
+        };
+        CorrespondenceMac::save(&db, game.clone()).await?;
+
+        // saving again rewrites the game
+        game.moves = String::from("e2e4");
+        game.deadline += 86400;
+        CorrespondenceMac::save(&db, game.clone()).await?;
+        let games = CorrespondenceMac::list(&db).await?;
+        let stored: Vec<_> = games.iter().filter(|stored| stored.id == id).collect();
+        assert_eq!(stored, vec![&game]);
+
+        CorrespondenceMac::remove(&db, &id).await?;
+        let games = CorrespondenceMac::list(&db).await?;
+        assert!(games.iter().all(|stored| stored.id != id));
+
+        Ok(())
+    }
+}
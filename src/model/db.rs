@@ -103,6 +103,11 @@ pub async fn init_tables(db: &DbConn) -> Result<(), DbErr> {
                 .create_table_from_entity(keys::Entity)
                 .if_not_exists(),
         ),
+        builder.build(
+            schema
+                .create_table_from_entity(key_rotations::Entity)
+                .if_not_exists(),
+        ),
         builder.build(
             schema
                 .create_table_from_entity(games::Entity)
@@ -113,6 +118,36 @@ pub async fn init_tables(db: &DbConn) -> Result<(), DbErr> {
                 .create_table_from_entity(users::Entity)
                 .if_not_exists(),
         ),
+        builder.build(
+            schema
+                .create_table_from_entity(ratings::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(chats::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(correspondence::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(sessions::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(lockouts::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(session_secrets::Entity)
+                .if_not_exists(),
+        ),
     ];
     for t in tables {
         db.execute(t).await.unwrap();
@@ -158,8 +193,15 @@ mod tests {
         let db = init_db().await?;
 
         assert!(table_exists(&db, "keys").await);
+        assert!(table_exists(&db, "key_rotations").await);
         assert!(table_exists(&db, "games").await);
         assert!(table_exists(&db, "users").await);
+        assert!(table_exists(&db, "ratings").await);
+        assert!(table_exists(&db, "chats").await);
+        assert!(table_exists(&db, "correspondence").await);
+        assert!(table_exists(&db, "sessions").await);
+        assert!(table_exists(&db, "lockouts").await);
+        assert!(table_exists(&db, "session_secrets").await);
         assert!(!table_exists(&db, "lusers").await);
 
         Ok(())
@@ -0,0 +1,98 @@
+use super::db::Db;
+use crate::model;
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::OnConflict;
+
+// Lifetime of the JWT signing keys, keys without a row predate rotation
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "key_rotations")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: model::IdType, // keys.id
+    pub created: i64,         // unix seconds
+    pub retires: Option<i64>, // unix seconds, tokens it signed are refused after
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct KeyRotationMac;
+
+impl KeyRotationMac {
+    pub async fn create(db: &Db, key: model::IdType, created: i64) -> Result<(), model::Error> {
+        let rotation = Model {
+            key,
+            created,
+            retires: None,
+        };
+        let rotation: ActiveModel = rotation.into();
+        Entity::insert(rotation).exec_without_returning(db).await?;
+
+        Ok(())
+    }
+
+    /// Sets when a key retires, keeping an earlier retirement.
+    pub async fn retire(db: &Db, key: model::IdType, at: i64) -> Result<(), model::Error> {
+        let retired = match Entity::find_by_id(key).one(db).await? {
+            Some(Model {
+                retires: Some(retires),
+                ..
+            }) if retires <= at => return Ok(()),
+            Some(rotation) => Model {
+                retires: Some(at),
+                ..rotation
+            },
+            None => Model {
+                key,
+                created: 0, // predates rotation
+                retires: Some(at),
+            },
+        };
+        let retired: ActiveModel = retired.into();
+        let on_conflict = OnConflict::column(Column::Key)
+            .update_column(Column::Retires)
+            .to_owned();
+        Entity::insert(retired)
+            .on_conflict(on_conflict)
+            .exec_without_returning(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn list(db: &Db) -> Result<Vec<Model>, model::Error> {
+        Ok(Entity::find().all(db).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyRotationMac;
+    use crate::model::db::init_db;
+
+    /*
+
+    cargo watch -q -c -w src -x 'test model_key_rotation_ -- --nocapture --test-threads=1'
+
+     */
+    #[tokio::test]
+    async fn model_key_rotation_retire() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let (key, legacy) = (-rand::random::<i32>().abs() as i64 - 1, i64::MIN + 1);
+        KeyRotationMac::create(&db, key, 1681776000).await?;
+        KeyRotationMac::retire(&db, key, 1681862400).await?;
+        KeyRotationMac::retire(&db, key, 1681948800).await?; // later, ignored
+        KeyRotationMac::retire(&db, legacy, 1681862400).await?;
+
+        let rotations = KeyRotationMac::list(&db).await?;
+        let of = |key| rotations.iter().find(|r| r.key == key).cloned().unwrap();
+        assert_eq!(of(key).created, 1681776000);
+        assert_eq!(of(key).retires, Some(1681862400));
+        assert_eq!(of(legacy).created, 0);
+        assert_eq!(of(legacy).retires, Some(1681862400));
+
+        Ok(())
+    }
+}
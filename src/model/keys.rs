@@ -29,16 +29,16 @@ impl KeyMac {
         Ok(res.last_insert_id)
     }
 
-    pub async fn get_last(db: &Db) -> Result<Option<Model>, model::Error> {
-        let k = Entity::find().order_by_desc(Column::Id).one(db).await?;
-        Ok(k)
+    /// All keys, oldest first.
+    pub async fn list(db: &Db) -> Result<Vec<Model>, model::Error> {
+        Ok(Entity::find().order_by_asc(Column::Id).all(db).await?)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::KeyMac;
-    use crate::auth::jwt::{current_key, random_key};
+    use crate::auth::jwt::{load_keys, random_key};
     use crate::model::db::init_db;
     /*
     cargo watch -q -c -w src -x 'test model_key_ -- --nocapture --test-threads=1'
@@ -47,8 +47,8 @@ mod tests {
     #[tokio::test]
     async fn model_key_create() -> Result<(), Box<dyn std::error::Error>> {
         let db = init_db().await?;
-        let result = current_key(&db).await?;
-        println!("\n--> current token {:?}", result);
+        let result = load_keys(&db).await?;
+        println!("\n--> current keys {:?}", result.ids());
 
         let key0 = random_key();
         let key1 = random_key();
@@ -57,7 +57,8 @@ mod tests {
         println!("\n--> result {:?}", result);
         let result = KeyMac::create(&db, &key1).await?;
         println!("\n--> result {:?}", result);
-        let last_key = KeyMac::get_last(&db).await?;
+        let keys = KeyMac::list(&db).await?;
+        let last_key = keys.last();
         println!("\n--> last_key {:?}", last_key);
 
         assert_eq!(last_key.unwrap().key, key1);
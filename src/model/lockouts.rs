@@ -0,0 +1,108 @@
+use super::db::Db;
+use crate::model;
+use sea_orm::entity::prelude::*;
+
+// Failed logins with an email, and until when it is locked for them,
+// kept alike whether an account has the email or not
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "lockouts")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub email: String, // lowercase
+    pub failures: i32,     // since the last login or lockout
+    pub locked_until: i64, // unix seconds
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct LockoutMac;
+
+impl LockoutMac {
+    pub async fn get(db: &Db, email: &str) -> Result<Option<Model>, model::Error> {
+        Ok(Entity::find_by_id(email.to_owned()).one(db).await?)
+    }
+
+    /// Counts a failed login at `now`, the `max`th in a row locks the account
+    /// for `lock_secs`. Returns when it is locked until.
+    pub async fn fail(
+        db: &Db,
+        email: &str,
+        now: i64,
+        max: i32,
+        lock_secs: i64,
+    ) -> Result<i64, model::Error> {
+        // one statement, so concurrent failures all count
+        let (failures, locked_until) = match max <= 1 {
+            true => (0, now + lock_secs),
+            false => (1, 0),
+        };
+        let pool = db.get_postgres_connection_pool();
+        let locked_until = sqlx::query_scalar(
+            "INSERT INTO lockouts (email, failures, locked_until) VALUES ($1, $2, $3)
+            ON CONFLICT (email) DO UPDATE SET
+                failures = CASE WHEN lockouts.failures + 1 >= $4
+                    THEN 0 ELSE lockouts.failures + 1 END,
+                locked_until = CASE WHEN lockouts.failures + 1 >= $4
+                    THEN $5 ELSE lockouts.locked_until END
+            RETURNING locked_until",
+        )
+        .bind(email)
+        .bind(failures)
+        .bind(locked_until)
+        .bind(max)
+        .bind(now + lock_secs)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(locked_until)
+    }
+
+    pub async fn clear(db: &Db, email: &str) -> Result<(), model::Error> {
+        Entity::delete_by_id(email.to_owned()).exec(db).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockoutMac;
+    use crate::model::db::init_db;
+
+    /*
+
+    cargo watch -q -c -w src -x 'test model_lockout_ -- --nocapture --test-threads=1'
+
+     */
+    #[tokio::test]
+    async fn model_lockout_fail() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let email = format!("{}@lockout.test", uuid::Uuid::new_v4());
+        let email = email.as_str();
+        let now = 1681776000;
+
+        assert_eq!(LockoutMac::fail(&db, email, now, 3, 900).await?, 0);
+        assert_eq!(LockoutMac::fail(&db, email, now, 3, 900).await?, 0);
+        assert_eq!(LockoutMac::fail(&db, email, now, 3, 900).await?, now + 900);
+        let lockout = LockoutMac::get(&db, email).await?.unwrap();
+        assert_eq!((lockout.failures, lockout.locked_until), (0, now + 900));
+
+        // The count starts over after a lockout, a login clears it all
+        assert_eq!(LockoutMac::fail(&db, email, now, 3, 900).await?, now + 900);
+        LockoutMac::clear(&db, email).await?;
+        assert!(LockoutMac::get(&db, email).await?.is_none());
+
+        // Failures at once count one by one
+        let fail = || LockoutMac::fail(&db, email, now, 3, 900);
+        let (a, b, c) = tokio::try_join!(fail(), fail(), fail())?;
+        let mut locked = [a, b, c];
+        locked.sort();
+        assert_eq!(locked, [0, 0, now + 900]);
+        LockoutMac::clear(&db, email).await?;
+
+        Ok(())
+    }
+}
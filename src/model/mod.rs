@@ -1,9 +1,17 @@
+use sea_orm::{DbErr, RuntimeErr};
 use thiserror::Error as ThisError;
 use warp::reject::Reject;
 
+pub mod chats;
+pub mod correspondence;
 pub mod db;
 pub mod games;
+pub mod key_rotations;
 pub mod keys;
+pub mod lockouts;
+pub mod ratings;
+pub mod session_secrets;
+pub mod sessions;
 pub mod users;
 
 pub type IdType = i64;
@@ -20,5 +28,27 @@ pub enum Error {
     DB(#[from] sea_orm::DbErr),
 }
 
+impl Error {
+    /// Whether a unique index refused the row, like a taken email.
+    pub fn is_unique_violation(&self) -> bool {
+        let e = match self {
+            Error::Sqlx(e) => e,
+            Error::DB(DbErr::Exec(RuntimeErr::SqlxError(e)))
+            | Error::DB(DbErr::Query(RuntimeErr::SqlxError(e))) => e,
+            _ => return false,
+        };
+        matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some("23505"))
+    }
+
+    /// Whether the database is out of reach rather than the request at fault.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(
+            self,
+            Error::DB(DbErr::ConnectionAcquire | DbErr::Conn(_))
+                | Error::Sqlx(sqlx::Error::PoolTimedOut | sqlx::Error::Io(_))
+        )
+    }
+}
+
 // error[E0277]: the trait bound `model::Error: warp::reject::Reject` is not satisfied
 impl Reject for Error {}
@@ -0,0 +1,123 @@
+use super::db::Db;
+use crate::chess::glicko::Rating;
+use crate::model;
+use sea_orm::entity::prelude::*;
+use sea_orm::*;
+
+// Rating history, the latest row per user and category is the current rating
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "ratings")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: model::IdType,
+    #[sea_orm(indexed)]
+    pub uid: model::IdType,
+    pub category: String, // bullet, blitz, rapid, classical or correspondence
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub game: Option<model::IdType>, // the rated game
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<&Model> for Rating {
+    fn from(model: &Model) -> Self {
+        Rating {
+            rating: model.rating,
+            deviation: model.deviation,
+            volatility: model.volatility,
+        }
+    }
+}
+
+pub struct RatingMac;
+
+impl RatingMac {
+    pub async fn create(
+        db: &Db,
+        uid: model::IdType,
+        category: &str,
+        rating: &Rating,
+        game: Option<model::IdType>,
+    ) -> Result<model::IdType, model::Error> {
+        let row = ActiveModel {
+            uid: Set(uid),
+            category: Set(category.to_owned()),
+            rating: Set(rating.rating),
+            deviation: Set(rating.deviation),
+            volatility: Set(rating.volatility),
+            game: Set(game),
+            ..Default::default()
+        };
+        let res = Entity::insert(row).exec(db).await?;
+
+        Ok(res.last_insert_id)
+    }
+
+    /// Current rating, None if the user has no rated games in the category
+    pub async fn current(
+        db: &Db,
+        uid: model::IdType,
+        category: &str,
+    ) -> Result<Option<Rating>, model::Error> {
+        let latest = Entity::find()
+            .filter(Column::Uid.eq(uid))
+            .filter(Column::Category.eq(category))
+            .order_by_desc(Column::Id)
+            .one(db)
+            .await?;
+
+        Ok(latest.as_ref().map(Rating::from))
+    }
+
+    /// All ratings of the user, oldest first
+    pub async fn history(db: &Db, uid: model::IdType) -> Result<Vec<Model>, model::Error> {
+        Ok(Entity::find()
+            .filter(Column::Uid.eq(uid))
+            .order_by_asc(Column::Id)
+            .all(db)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::db::init_db;
+
+    /*
+
+    cargo watch -q -c -w src -x 'test model_rating_ -- --nocapture --test-threads=1'
+
+     */
+    #[tokio::test]
+    async fn model_rating_history() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let uid = rand::random::<u32>().into();
+
+        assert_eq!(RatingMac::current(&db, uid, "blitz").await?, None);
+
+        let first = Rating::default();
+        let second = Rating {
+            rating: 1600.0,
+            ..first
+        };
+        RatingMac::create(&db, uid, "blitz", &first, None).await?;
+        RatingMac::create(&db, uid, "blitz", &second, Some(17)).await?;
+        RatingMac::create(&db, uid, "bullet", &first, None).await?;
+
+        assert_eq!(RatingMac::current(&db, uid, "blitz").await?, Some(second));
+        assert_eq!(RatingMac::current(&db, uid, "bullet").await?, Some(first));
+        assert_eq!(RatingMac::current(&db, uid, "rapid").await?, None);
+
+        let history = RatingMac::history(&db, uid).await?;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[1].game, Some(17));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use super::db::Db;
+use crate::model;
+use sea_orm::entity::prelude::*;
+use sea_orm::*;
+
+// Refresh token secrets a session traded already, to tell a reused token
+// from a forged one
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "session_secrets")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub hash: String, // sha256 hex of the traded secret
+    #[sea_orm(indexed)]
+    pub sid: String,
+    #[sea_orm(indexed)]
+    pub expires: i64, // unix seconds, of no use once the session expired
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct SessionSecretMac;
+
+impl SessionSecretMac {
+    /// Records a traded secret of session `sid`, and forgets those expired before `now`.
+    pub async fn add(
+        db: &Db,
+        sid: &str,
+        hash: &str,
+        expires: i64,
+        now: i64,
+    ) -> Result<(), model::Error> {
+        Entity::delete_many()
+            .filter(Column::Expires.lte(now))
+            .exec(db)
+            .await?;
+        let secret = ActiveModel {
+            hash: Set(hash.to_owned()),
+            sid: Set(sid.to_owned()),
+            expires: Set(expires),
+        };
+        Entity::insert(secret)
+            .on_conflict(
+                sea_query::OnConflict::column(Column::Hash)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Whether session `sid` issued and traded the secret of `hash`.
+    pub async fn traded(db: &Db, sid: &str, hash: &str) -> Result<bool, model::Error> {
+        let secret = Entity::find_by_id(hash.to_owned()).one(db).await?;
+
+        Ok(secret.is_some_and(|secret| secret.sid == sid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionSecretMac;
+    use crate::model::db::init_db;
+
+    /*
+
+    cargo watch -q -c -w src -x 'test model_session_secret_ -- --nocapture --test-threads=1'
+
+     */
+    #[tokio::test]
+    async fn model_session_secret_traded() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let sid = uuid::Uuid::new_v4().to_string();
+        let (hash, old) = (
+            uuid::Uuid::new_v4().to_string(),
+            uuid::Uuid::new_v4().to_string(),
+        );
+        let now = 1681776000;
+
+        SessionSecretMac::add(&db, &sid, &old, now - 1, now - 2).await?;
+        SessionSecretMac::add(&db, &sid, &hash, now + 900, now).await?;
+        assert!(SessionSecretMac::traded(&db, &sid, &hash).await?);
+        assert!(!SessionSecretMac::traded(&db, "other", &hash).await?);
+        // expired secrets are forgotten
+        assert!(!SessionSecretMac::traded(&db, &sid, &old).await?);
+
+        Ok(())
+    }
+}
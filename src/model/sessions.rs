@@ -0,0 +1,112 @@
+use super::db::Db;
+use crate::model;
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::*;
+
+// Login sessions, each with the hash of its one current refresh token
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "sessions")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String, // session uuid, the access tokens' sid
+    #[sea_orm(indexed)]
+    pub uid: model::IdType,
+    pub hash: String,  // sha256 hex of the refresh token secret
+    pub expires: i64,  // unix seconds
+    pub revoked: bool, // logged out or a refresh token reused
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct SessionMac;
+
+impl SessionMac {
+    pub async fn create(
+        db: &Db,
+        id: &str,
+        uid: model::IdType,
+        hash: &str,
+        expires: i64,
+    ) -> Result<(), model::Error> {
+        let session = ActiveModel {
+            id: Set(id.to_owned()),
+            uid: Set(uid),
+            hash: Set(hash.to_owned()),
+            expires: Set(expires),
+            revoked: Set(false),
+        };
+        Entity::insert(session).exec_without_returning(db).await?;
+
+        Ok(())
+    }
+
+    pub async fn get(db: &Db, id: &str) -> Result<Option<Model>, model::Error> {
+        Ok(Entity::find_by_id(id.to_owned()).one(db).await?)
+    }
+
+    /// Replaces the refresh token of a live session, false if `hash` is no longer current.
+    pub async fn rotate(
+        db: &Db,
+        id: &str,
+        hash: &str,
+        new_hash: &str,
+        expires: i64,
+    ) -> Result<bool, model::Error> {
+        let res = Entity::update_many()
+            .col_expr(Column::Hash, Expr::value(new_hash))
+            .col_expr(Column::Expires, Expr::value(expires))
+            .filter(Column::Id.eq(id))
+            .filter(Column::Hash.eq(hash))
+            .filter(Column::Revoked.eq(false))
+            .exec(db)
+            .await?;
+
+        Ok(res.rows_affected == 1)
+    }
+
+    pub async fn revoke(db: &Db, id: &str) -> Result<(), model::Error> {
+        Entity::update_many()
+            .col_expr(Column::Revoked, Expr::value(true))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionMac;
+    use crate::model::db::init_db;
+
+    /*
+
+    cargo watch -q -c -w src -x 'test model_session_ -- --nocapture --test-threads=1'
+
+     */
+    #[tokio::test]
+    async fn model_session_rotate() -> Result<(), Box<dyn std::error::Error>> {
+        let db = init_db().await?;
+        let id = uuid::Uuid::new_v4().to_string();
+        SessionMac::create(&db, &id, 17, "first", 1681776000).await?;
+
+        assert!(SessionMac::rotate(&db, &id, "first", "second", 1681862400).await?);
+        assert!(!SessionMac::rotate(&db, &id, "first", "third", 1681862400).await?);
+        let session = SessionMac::get(&db, &id).await?.unwrap();
+        assert_eq!(
+            (session.hash.as_str(), session.expires),
+            ("second", 1681862400)
+        );
+
+        SessionMac::revoke(&db, &id).await?;
+        assert!(!SessionMac::rotate(&db, &id, "second", "third", 1681862400).await?);
+        assert!(SessionMac::get(&db, &id).await?.unwrap().revoked);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use super::db::Db;
 use crate::model;
 use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::{Expr, Func};
 use sea_orm::*;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
@@ -39,11 +40,25 @@ impl UserMac {
         Ok(res.last_insert_id)
     }
 
+    pub async fn update_hash(db: &Db, id: model::IdType, hash: &str) -> Result<(), model::Error> {
+        let user = ActiveModel {
+            id: Set(id),
+            hash: Set(hash.to_owned()),
+            ..Default::default()
+        };
+        Entity::update(user).exec(db).await?;
+
+        Ok(())
+    }
+
+    pub async fn get(db: &Db, id: model::IdType) -> Result<Option<Model>, model::Error> {
+        Ok(Entity::find_by_id(id).one(db).await?)
+    }
+
+    /// The user of a lowercase `email`, whatever case it signed up with.
     pub async fn get_by_email(db: &Db, email: &str) -> Result<Option<Model>, model::Error> {
-        let user = Entity::find()
-            .filter(Column::Email.contains(email))
-            .one(db)
-            .await?;
+        let stored = Expr::expr(Func::lower(Expr::col(Column::Email)));
+        let user = Entity::find().filter(stored.eq(email)).one(db).await?;
 
         Ok(user)
     }
@@ -52,7 +67,7 @@ impl UserMac {
 #[cfg(test)]
 mod tests {
     use super::UserMac;
-    use crate::auth::md5::hash_password;
+    use crate::auth::password::hash_password;
     use crate::model::db::init_db;
     use rand::{distributions::Alphanumeric, Rng};
 
@@ -72,17 +87,30 @@ mod tests {
             .map(char::from)
             .collect();
 
-        let hash = hash_password("password");
+        let hash = hash_password("password").await;
 
         let result = UserMac::create(&db, user, &email, &hash).await?;
         println!("\n--> result {:?}", result);
         assert!(result > 0);
 
         // expected to fail due to duplicate email
-        let other_hash = hash_password("other password");
+        let other_hash = hash_password("other password").await;
         let errresult = UserMac::create(&db, other_user, &email, &other_hash).await;
         println!("\n--> errresult {:?}", errresult);
-        assert!(errresult.is_err());
+        assert!(errresult.unwrap_err().is_unique_violation());
+
+        // only the exact email finds the user, in lowercase
+        let lowercase = email.to_lowercase();
+        assert_eq!(
+            UserMac::get_by_email(&db, &lowercase).await?.unwrap().id,
+            result
+        );
+        assert!(UserMac::get_by_email(&db, &lowercase[1..]).await?.is_none());
+
+        UserMac::update_hash(&db, result, &other_hash).await?;
+        let updated = UserMac::get(&db, result).await?.unwrap();
+        assert_eq!(updated.hash, other_hash);
+        assert_eq!(updated.name, user);
 
         Ok(())
     }
@@ -0,0 +1,99 @@
+use crate::auth::{jwt, session};
+use crate::limit::TooManyRequests;
+use crate::model;
+use serde::{Deserialize, Serialize};
+use warp::filters::body::BodyDeserializeError;
+use warp::http::header::RETRY_AFTER;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Rejection, Reply};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorReply {
+    pub error: String,
+}
+
+pub fn error_reply(status: StatusCode, error: &str) -> Response {
+    let reply = ErrorReply {
+        error: error.to_owned(),
+    };
+    warp::reply::with_status(warp::reply::json(&reply), status).into_response()
+}
+
+fn model_reply(e: &model::Error) -> Response {
+    if e.is_unique_violation() {
+        return error_reply(StatusCode::CONFLICT, "already exists");
+    }
+    eprintln!("model error {:?}", e);
+    if e.is_unavailable() {
+        return error_reply(StatusCode::SERVICE_UNAVAILABLE, "try again later");
+    }
+    error_reply(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+}
+
+/// Answers the API's own rejections, the rest fall through to the next route.
+pub async fn handle_rejection(err: Rejection) -> Result<Response, Rejection> {
+    if let Some(e) = err.find::<TooManyRequests>() {
+        let mut reply = error_reply(StatusCode::TOO_MANY_REQUESTS, "too many requests");
+        reply
+            .headers_mut()
+            .insert(RETRY_AFTER, e.retry_after.into());
+        return Ok(reply);
+    }
+    if let Some(e) = err.find::<model::Error>() {
+        return Ok(model_reply(e));
+    }
+    if let Some(e) = err.find::<session::Error>() {
+        return Ok(match e {
+            session::Error::Model(e) => model_reply(e),
+            e => error_reply(StatusCode::UNAUTHORIZED, &e.to_string()),
+        });
+    }
+    if let Some(jwt::Error::FromModelError(e)) = err.find::<jwt::Error>() {
+        return Ok(model_reply(e));
+    }
+    if let Some(e) = err.find::<BodyDeserializeError>() {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, &e.to_string()));
+    }
+
+    Err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn rejection_status() {
+        let api = warp::path("fail")
+            .and_then(|| async { Err::<String, _>(warp::reject::custom(session::Error::Reused)) })
+            .or(warp::path("slow").and_then(|| async {
+                Err::<String, _>(warp::reject::custom(TooManyRequests { retry_after: 7 }))
+            }))
+            .or(warp::path("json")
+                .and(warp::body::json())
+                .map(|b: ErrorReply| b.error))
+            .recover(handle_rejection);
+
+        let res = warp::test::request().path("/fail").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body: ErrorReply = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body.error, "refresh token reused, session revoked");
+
+        let res = warp::test::request()
+            .path("/json")
+            .body("{}")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = warp::test::request().path("/slow").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[RETRY_AFTER], "7");
+
+        // Not ours, left to the routes after
+        let res = warp::test::request().path("/other").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
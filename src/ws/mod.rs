@@ -2,26 +2,268 @@ use futures_util::StreamExt;
 use warp::ws::{Message, WebSocket};
 
 use crate::auth::UserCtx;
-use crate::chess::hub::{Handle, Message as HubMessage};
-use crate::chess::GamePreference;
+use crate::chess::clock::ClockSnapshot;
+use crate::chess::glicko::Rating;
+use crate::chess::hub::{ChallengeId, Handle, LiveGameId, Message as HubMessage, SeekId};
+use crate::chess::{GameEndReason, GamePreference, GameResult, TimeCategory, TimeControl};
 use crate::model::db::Db;
+use crate::model::IdType;
 use serde::{Deserialize, Serialize};
 
 pub mod rx;
 pub mod tx;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum WsColor {
     #[default]
     White,
     Black,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum WsMessage {
     GameRequest(GamePreference),
-    GameResponse(WsColor),
-    Move(String),
+    GameResponse {
+        game_id: LiveGameId,
+        color: WsColor,
+    },
+    GameRequestRejected {
+        reason: String,
+    },
+    Move {
+        game_id: LiveGameId,
+        uci: String,
+    },
+    MoveRejected {
+        game_id: LiveGameId,
+        uci: String,
+        reason: String,
+    },
+    Clock {
+        game_id: LiveGameId,
+        clock: ClockSnapshot,
+    },
+    GameEnd {
+        game_id: LiveGameId,
+        result: GameResult,
+        reason: GameEndReason,
+    },
+    OpponentDisconnected {
+        game_id: LiveGameId,
+    },
+    OpponentReconnected {
+        game_id: LiveGameId,
+    },
+    GetGameState,         // of all live games
+    GameState(GameState), // one per live game
+    GetRatings,
+    Ratings(Vec<RatingEntry>), // history, oldest first
+    GetEngines,
+    Engines(Vec<EngineInfo>),
+    Resign {
+        game_id: LiveGameId,
+    },
+    OfferDraw {
+        game_id: LiveGameId, // to the opponent too
+    },
+    AcceptDraw {
+        game_id: LiveGameId,
+    },
+    DeclineDraw {
+        game_id: LiveGameId, // to the offerer too
+    },
+    DrawOfferWithdrawn {
+        game_id: LiveGameId,
+    },
+    Abort {
+        game_id: LiveGameId,
+    },
+    CommandRejected {
+        reason: String,
+    },
+    TakebackRequest {
+        game_id: LiveGameId, // to the opponent too
+    },
+    TakebackAccept {
+        game_id: LiveGameId,
+    },
+    TakebackDecline {
+        game_id: LiveGameId, // to the requester too
+    },
+    RematchOffer {
+        game_id: LiveGameId, // the finished game, to the opponent too
+    },
+    RematchAccept {
+        game_id: LiveGameId,
+    },
+    RematchExpired {
+        game_id: LiveGameId,
+    },
+    Spectate {
+        game_id: LiveGameId,
+    },
+    Unspectate {
+        game_id: LiveGameId,
+    },
+    SpectatorState(SpectatorState),
+    Spectators {
+        game_id: LiveGameId,
+        count: usize, // to the players, how many are watching
+    },
+    Lobby, // subscribe to the lobby feed, LobbyState then events
+    LeaveLobby,
+    LobbyState {
+        seeks: Vec<Seek>,
+        games: Vec<LobbyGame>,
+    },
+    SeekCreated(Seek),
+    SeekRemoved {
+        id: SeekId,
+    },
+    GameStarted(LobbyGame),
+    GameEnded {
+        game_id: LiveGameId,
+    },
+    AcceptSeek {
+        id: SeekId,
+    },
+    Challenge {
+        to_user: IdType,
+        preference: GamePreference,
+    },
+    ChallengeOffer(ChallengeInfo), // to both sides, the challenged one when connected
+    ChallengeAccept {
+        id: ChallengeId,
+    },
+    ChallengeDecline {
+        id: ChallengeId, // to the challenger too
+    },
+    ChallengeCancel {
+        id: ChallengeId, // to the challenged too
+    },
+    ChallengeExpired {
+        id: ChallengeId,
+    },
+    Chat {
+        room: ChatRoom,
+        text: String,
+    },
+    ChatLine(ChatLine), // to the others in the room
+    Mute {
+        uid: IdType,
+    },
+    Unmute {
+        uid: IdType,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatRoom {
+    Game(LiveGameId),       // the players of a live game
+    Spectators(LiveGameId), // the spectators of a live game
+    Lobby,
+}
+
+/// Everything needed to restore a live game on the client
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameState {
+    pub game_id: LiveGameId,
+    pub fen: String,        // starting position
+    pub moves: Vec<String>, // uci moves played from the starting position
+    pub color: WsColor,
+    pub opponent: IdType,
+    pub opponent_name: String,
+    pub clock: Option<ClockSnapshot>,
+    pub deadline: Option<i64>, // correspondence, unix seconds for the side to move
+    pub draw_offer: Option<WsColor>, // the side offering a draw
+    pub takeback: Option<WsColor>, // the side asking for a takeback
+}
+
+/// A live game as seen by a spectator, followed by its moves, clocks and end
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpectatorState {
+    pub game_id: LiveGameId,
+    pub white: IdType,
+    pub white_name: String,
+    pub black: IdType,
+    pub black_name: String,
+    pub fen: String,        // starting position
+    pub moves: Vec<String>, // uci moves played from the starting position
+    pub clock: Option<ClockSnapshot>,
+}
+
+/// An open game request waiting in the lobby
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Seek {
+    pub id: SeekId,
+    pub uid: IdType,
+    pub name: String,
+    pub preference: GamePreference,
+    pub rating: Option<f64>, // None for unrated seeks
+}
+
+/// A live game as listed in the lobby
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LobbyGame {
+    pub game_id: LiveGameId,
+    pub white: IdType,
+    pub white_name: String,
+    pub black: IdType,
+    pub black_name: String,
+    pub tc: TimeControl,
+    pub rated: bool,
+}
+
+/// A game offered to a specific user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChallengeInfo {
+    pub id: ChallengeId,
+    pub from: IdType,
+    pub from_name: String,
+    pub to: IdType,
+    pub preference: GamePreference, // colors as seen by the challenger
+}
+
+/// A chat line and its sender
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatLine {
+    pub room: ChatRoom,
+    pub uid: IdType,
+    pub name: String,
+    pub text: String,
+}
+
+/// A rating after a rated game, or the initial one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RatingEntry {
+    pub category: TimeCategory,
+    pub rating: Rating,
+    pub game: Option<IdType>, // the rated game
+}
+
+/// A configured engine to play against
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EngineInfo {
+    pub name: String,
+    pub levels: usize, // GamePreference levels 1 to levels
+}
+
+impl From<WsColor> for shakmaty::Color {
+    fn from(color: WsColor) -> Self {
+        match color {
+            WsColor::White => shakmaty::Color::White,
+            WsColor::Black => shakmaty::Color::Black,
+        }
+    }
+}
+
+impl From<shakmaty::Color> for WsColor {
+    fn from(color: shakmaty::Color) -> Self {
+        match color {
+            shakmaty::Color::White => WsColor::White,
+            shakmaty::Color::Black => WsColor::Black,
+        }
+    }
 }
 
 pub async fn user_connected(ws: WebSocket, _db: Db, hub: Handle, utx: UserCtx) {
@@ -30,7 +272,7 @@ pub async fn user_connected(ws: WebSocket, _db: Db, hub: Handle, utx: UserCtx) {
     // Split the socket into a sender and receive of messages.
     let (user_ws_tx, user_ws_rx) = ws.split();
     let tx_con = tx::WsHandleTx::new(user_ws_tx);
-    let rx_con = rx::WsConnRx::new(user_ws_rx, hub, tx_con, utx.id);
+    let rx_con = rx::WsConnRx::new(user_ws_rx, hub, tx_con, utx.id, utx.name);
     rx_con.run().await;
 }
 
@@ -52,9 +294,138 @@ mod tests {
 
     #[tokio::test]
     async fn ws_messages_json() -> Result<(), Box<dyn std::error::Error>> {
+        let game_id = uuid::Uuid::new_v4();
         let messages = [
             WsMessage::GameRequest(GamePreference::default()),
-            WsMessage::GameResponse(WsColor::default()),
+            WsMessage::GameResponse {
+                game_id,
+                color: WsColor::default(),
+            },
+            WsMessage::GameRequestRejected {
+                reason: String::from("Lc0 is not available"),
+            },
+            WsMessage::Move {
+                game_id,
+                uci: String::from("e2e4"),
+            },
+            WsMessage::MoveRejected {
+                game_id,
+                uci: String::from("e2e5"),
+                reason: String::from("not your turn"),
+            },
+            WsMessage::Clock {
+                game_id,
+                clock: ClockSnapshot::default(),
+            },
+            WsMessage::GameEnd {
+                game_id,
+                result: GameResult::WhiteWins,
+                reason: GameEndReason::Timeout,
+            },
+            WsMessage::OpponentDisconnected { game_id },
+            WsMessage::OpponentReconnected { game_id },
+            WsMessage::GetGameState,
+            WsMessage::GameState(GameState {
+                game_id,
+                fen: String::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+                moves: vec![String::from("e2e4")],
+                color: WsColor::Black,
+                opponent: 17,
+                opponent_name: String::from("Some One"),
+                clock: Some(ClockSnapshot::default()),
+                deadline: None,
+                draw_offer: None,
+                takeback: Some(WsColor::White),
+            }),
+            WsMessage::GetRatings,
+            WsMessage::Ratings(vec![RatingEntry {
+                category: TimeCategory::Blitz,
+                rating: Rating::default(),
+                game: Some(17),
+            }]),
+            WsMessage::GetEngines,
+            WsMessage::Engines(vec![EngineInfo {
+                name: String::from("Sockfish"),
+                levels: 5,
+            }]),
+            WsMessage::Resign { game_id },
+            WsMessage::OfferDraw { game_id },
+            WsMessage::AcceptDraw { game_id },
+            WsMessage::DeclineDraw { game_id },
+            WsMessage::DrawOfferWithdrawn { game_id },
+            WsMessage::Abort { game_id },
+            WsMessage::CommandRejected {
+                reason: String::from("no draw offer"),
+            },
+            WsMessage::TakebackRequest { game_id },
+            WsMessage::TakebackAccept { game_id },
+            WsMessage::TakebackDecline { game_id },
+            WsMessage::RematchOffer { game_id },
+            WsMessage::RematchAccept { game_id },
+            WsMessage::RematchExpired { game_id },
+            WsMessage::Spectate { game_id },
+            WsMessage::Unspectate { game_id },
+            WsMessage::SpectatorState(SpectatorState {
+                game_id,
+                white: 17,
+                white_name: String::from("Some One"),
+                black: 18,
+                black_name: String::from("Other"),
+                fen: String::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+                moves: vec![String::from("e2e4")],
+                clock: None,
+            }),
+            WsMessage::Spectators { game_id, count: 2 },
+            WsMessage::Lobby,
+            WsMessage::LeaveLobby,
+            WsMessage::LobbyState {
+                seeks: vec![Seek {
+                    id: 3,
+                    uid: 17,
+                    name: String::from("Some One"),
+                    preference: GamePreference::default(),
+                    rating: Some(1500.0),
+                }],
+                games: vec![LobbyGame {
+                    game_id,
+                    white: 17,
+                    white_name: String::from("Some One"),
+                    black: 18,
+                    black_name: String::from("Other"),
+                    tc: TimeControl::default(),
+                    rated: true,
+                }],
+            },
+            WsMessage::SeekRemoved { id: 3 },
+            WsMessage::GameEnded { game_id },
+            WsMessage::AcceptSeek { id: 3 },
+            WsMessage::Challenge {
+                to_user: 18,
+                preference: GamePreference::default(),
+            },
+            WsMessage::ChallengeOffer(ChallengeInfo {
+                id: 5,
+                from: 17,
+                from_name: String::from("Some One"),
+                to: 18,
+                preference: GamePreference::default(),
+            }),
+            WsMessage::ChallengeAccept { id: 5 },
+            WsMessage::ChallengeDecline { id: 5 },
+            WsMessage::ChallengeCancel { id: 5 },
+            WsMessage::ChallengeExpired { id: 5 },
+            WsMessage::Chat {
+                room: ChatRoom::Spectators(game_id),
+                text: String::from("gg"),
+            },
+            WsMessage::ChatLine(ChatLine {
+                room: ChatRoom::Game(game_id),
+                uid: 17,
+                name: String::from("Some One"),
+                text: String::from("gg"),
+            }),
+            WsMessage::Mute { uid: 17 },
+            WsMessage::Unmute { uid: 17 },
         ];
         for msg in messages {
             let msg = serde_json::to_string(&msg).unwrap();
@@ -123,8 +494,8 @@ mod tests {
         let signup_url = "http://localhost:3030/signup";
         let u = UserSignup {
             name: rand_string(8),
-            email: rand_string(8),
-            password: rand_string(8),
+            email: format!("{}@test.example", rand_string(8)),
+            password: rand_string(12),
         };
 
         // Send POST request to signup endpoint
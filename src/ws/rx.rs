@@ -10,6 +10,7 @@ pub struct WsConnRx {
     hub: Handle,                      // to Hub
     ws_handle_tx: tx::WsHandleTx,     // Respond to from Hub, user Ws Tx
     uid: IdType,                      // User DB Id
+    name: String,                     // User display name
 }
 
 impl WsConnRx {
@@ -18,12 +19,14 @@ impl WsConnRx {
         hub: Handle,
         ws_handle_tx: tx::WsHandleTx,
         uid: IdType,
+        name: String,
     ) -> Self {
         WsConnRx {
             receiver,
             hub,
             ws_handle_tx,
             uid,
+            name,
         }
     }
 
@@ -33,32 +36,217 @@ impl WsConnRx {
             WsMessage::GameRequest(msg) => {
                 let uid = self.uid;
                 let respond_to = self.ws_handle_tx.sender.clone();
+                let name = self.name.clone();
                 let msg = HubMessage::GameRequest {
                     msg,
                     respond_to,
                     uid,
+                    name,
                 };
                 self.hub.send(msg).await.unwrap();
             }
-            WsMessage::Move(uci) => {
+            WsMessage::Move { game_id, uci } => {
                 let uid = self.uid;
-                let msg = HubMessage::Move { uci, uid };
+                let msg = HubMessage::Move { game_id, uci, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::GetGameState => {
+                let uid = self.uid;
+                let msg = HubMessage::GetGameState { uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Resign { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::Resign { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::OfferDraw { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::OfferDraw { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::AcceptDraw { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::AcceptDraw { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::DeclineDraw { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::DeclineDraw { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Abort { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::Abort { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::TakebackRequest { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::TakebackRequest { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::TakebackAccept { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::TakebackAccept { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::TakebackDecline { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::TakebackDecline { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::RematchOffer { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::RematchOffer { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::RematchAccept { game_id } => {
+                let uid = self.uid;
+                let msg = HubMessage::RematchAccept { game_id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Lobby => {
+                let uid = self.uid;
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let msg = HubMessage::Lobby { respond_to, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::LeaveLobby => {
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let msg = HubMessage::LeaveLobby { respond_to };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::AcceptSeek { id } => {
+                let uid = self.uid;
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let name = self.name.clone();
+                let msg = HubMessage::AcceptSeek {
+                    id,
+                    respond_to,
+                    uid,
+                    name,
+                };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Challenge {
+                to_user,
+                preference,
+            } => {
+                let uid = self.uid;
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let name = self.name.clone();
+                let msg = HubMessage::Challenge {
+                    to_user,
+                    msg: preference,
+                    respond_to,
+                    uid,
+                    name,
+                };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::ChallengeAccept { id } => {
+                let uid = self.uid;
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let name = self.name.clone();
+                let msg = HubMessage::ChallengeAccept {
+                    id,
+                    respond_to,
+                    uid,
+                    name,
+                };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::ChallengeDecline { id } => {
+                let uid = self.uid;
+                let msg = HubMessage::ChallengeDecline { id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::ChallengeCancel { id } => {
+                let uid = self.uid;
+                let msg = HubMessage::ChallengeCancel { id, uid };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Chat { room, text } => {
+                let uid = self.uid;
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let name = self.name.clone();
+                let msg = HubMessage::Chat {
+                    room,
+                    text,
+                    respond_to,
+                    uid,
+                    name,
+                };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Mute { uid: muted } => {
+                let uid = self.uid;
+                self.hub
+                    .send(HubMessage::Mute { uid, muted })
+                    .await
+                    .unwrap();
+            }
+            WsMessage::Unmute { uid: muted } => {
+                let uid = self.uid;
+                let msg = HubMessage::Unmute { uid, muted };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Spectate { game_id } => {
+                let uid = self.uid;
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let msg = HubMessage::Spectate {
+                    game_id,
+                    respond_to,
+                    uid,
+                };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::Unspectate { game_id } => {
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let msg = HubMessage::Unspectate {
+                    game_id,
+                    respond_to,
+                };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::GetEngines => {
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let msg = HubMessage::GetEngines { respond_to };
+                self.hub.send(msg).await.unwrap();
+            }
+            WsMessage::GetRatings => {
+                let uid = self.uid;
+                let respond_to = self.ws_handle_tx.sender.clone();
+                let msg = HubMessage::GetRatings { respond_to, uid };
                 self.hub.send(msg).await.unwrap();
             }
             _ => eprintln!("WsConnRx::handle_message() unexpected msg: {:?}", msg),
         }
     }
 
+    async fn handle_connect(&self) {
+        let uid = self.uid;
+        let respond_to = self.ws_handle_tx.sender.clone();
+
+        self.hub
+            .send(HubMessage::WsConnect { respond_to, uid })
+            .await
+            .unwrap();
+    }
+
     async fn handle_disconnect(&self) {
         let uid = self.uid;
+        let respond_to = self.ws_handle_tx.sender.clone();
 
         self.hub
-            .send(HubMessage::WsDisconnect { uid })
+            .send(HubMessage::WsDisconnect { respond_to, uid })
             .await
             .unwrap();
     }
 
     pub async fn run(mut self) {
+        self.handle_connect().await;
+
         while let Some(result) = self.receiver.next().await {
             if let Err(e) = result {
                 eprintln!("websocket error: {}", e);
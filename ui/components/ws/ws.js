@@ -4,7 +4,30 @@
 
 import {useEffect} from "react";
 
+// Access tokens expire after 15 minutes, trade the refresh cookie well before
+const REFRESH_INTERVAL = 10 * 60 * 1000;
+
 export const wsConn = {
+    refresh: function () {
+        // one refresh at a time, a refresh token traded twice ends the session
+        if (!wsConn.refreshing) {
+            wsConn.refreshing = fetch("/refresh", {method: "POST", credentials: "same-origin"})
+                .then(resp => {
+                    if (resp.status === 401)
+                        document.location.replace("/auth");
+                    return resp.ok;
+                })
+                .catch(err => {
+                    console.error('wsConn: refresh failed: ', err.message);
+                    return false;
+                })
+                .finally(() => {
+                    wsConn.refreshing = undefined;
+                });
+        }
+        return wsConn.refreshing;
+    },
+
     wsConnect: function () {
         wsConn.update ({ serverConnection: {
             bg: "warning",
@@ -36,7 +59,8 @@ export const wsConn = {
             }})
             console.log('wsConn: Socket is closed. Reconnect will be attempted in 1 second.', e.reason);
             setTimeout(function () {
-                wsConn.wsConnect();
+                // the access token may have expired meanwhile
+                wsConn.refresh().then(() => wsConn.wsConnect());
             }, 1000);
         };
         wsConn.ws.onerror = function (err) {
@@ -47,6 +71,7 @@ export const wsConn = {
 
     connect: function (update) {
         wsConn.update = update
+        wsConn.refreshTimer = setInterval(wsConn.refresh, REFRESH_INTERVAL);
         // TODO: can we skip auth if cookie is present?
         //fetch("/api/auth", {credentials: "same-origin"})
         //    .then(resp => {
@@ -55,6 +80,7 @@ export const wsConn = {
     },
 
     disconnect: function() {
+        clearInterval(wsConn.refreshTimer);
         wsConn.update ({ serverConnection: {
             bg: "danger",
             text: "Disconnected"